@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::{Args, Subcommand};
 
+use crate::logging::LogFormat;
+
 #[derive(Debug, Args)]
 pub struct ConfigCommand {
     #[clap(subcommand)]
@@ -23,6 +27,42 @@ pub struct ConfigSetArgs {
     /// Target client id
     #[arg(short('c'), long)]
     pub target_client_id: Option<String>,
+
+    /// Attempts of a batch failing with a transient database error, including the first one
+    #[arg(long)]
+    pub retry_max_attempts: Option<u32>,
+
+    /// Delay in milliseconds before the first retry, doubled on each attempt
+    #[arg(long)]
+    pub retry_base_delay_ms: Option<u64>,
+
+    /// Upper bound in milliseconds of the delay between retries
+    #[arg(long)]
+    pub retry_max_delay_ms: Option<u64>,
+
+    /// Log level before -v and -q are applied: off, error, warn, info, debug or trace
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Format of log records
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// File log records are also written to, rotated by size
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Size in megabytes at which the log file is rotated
+    #[arg(long)]
+    pub log_file_max_size_mb: Option<u64>,
+
+    /// Number of rotated log files kept
+    #[arg(long)]
+    pub log_file_count: Option<u32>,
+
+    /// log4rs YAML file used instead of every other log setting
+    #[arg(long, value_name = "PATH")]
+    pub log_config: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -33,6 +73,6 @@ pub enum ConfigSubCommand {
     /// Update configuration values
     Set {
         #[clap(flatten)]
-        args: ConfigSetArgs,
+        args: Box<ConfigSetArgs>,
     },
 }
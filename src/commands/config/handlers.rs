@@ -25,6 +25,15 @@ pub async fn config_set(settings: &mut Settings, args: &ConfigSetArgs) -> Result
     settings.target_url(&args.target_url);
     settings.timeout(&args.timeout);
     settings.target_client_id(&args.target_client_id);
+    settings.retry_max_attempts(&args.retry_max_attempts);
+    settings.retry_base_delay_ms(&args.retry_base_delay_ms);
+    settings.retry_max_delay_ms(&args.retry_max_delay_ms);
+    settings.log_level(&args.log_level);
+    settings.log_format(&args.log_format);
+    settings.log_file(&args.log_file);
+    settings.log_file_max_size_mb(&args.log_file_max_size_mb);
+    settings.log_file_count(&args.log_file_count);
+    settings.log_config(&args.log_config);
     settings.write()?;
     config_show(settings).await
 }
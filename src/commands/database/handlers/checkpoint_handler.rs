@@ -0,0 +1,125 @@
+use crate::commands::database::commands::{CheckpointSubCommand, DatabaseCheckpointArgs};
+use crate::commands::root::GlobalOpts;
+use crate::database::cursor::{Cursor, CursorStrategy};
+use crate::database::models::{CheckpointKey, SyncCheckpoint};
+use crate::database::repo::{self, Pools};
+use crate::settings::{MergeSettings, Settings};
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use sqlx::{types::BigDecimal, MySqlPool};
+
+pub async fn database_checkpoint(
+    settings: &Settings,
+    _: &GlobalOpts,
+    args: DatabaseCheckpointArgs,
+    command: CheckpointSubCommand,
+) -> Result<()> {
+    let args = args.merge(settings);
+    let pools = Pools::try_from(&args).context("Error creating connection pools.")?;
+    let client_id = args
+        .target_client_id
+        .as_ref()
+        .context("Target client id is not defined.")?;
+    let key = args.common_args.checkpoint_key(client_id)?;
+
+    match command {
+        CheckpointSubCommand::Show => checkpoint_show(&pools.target, &key).await,
+        CheckpointSubCommand::Set {
+            created_date,
+            uidpk,
+        } => checkpoint_set(&pools.target, &key, &created_date, uidpk).await,
+        CheckpointSubCommand::Reset => checkpoint_reset(&pools.target, &key).await,
+    }
+}
+
+async fn checkpoint_show(target: &MySqlPool, key: &CheckpointKey) -> Result<()> {
+    println!("Source: {}", key.source);
+    println!("Target: {}", key.target);
+    println!("Client id: {}", key.client_id);
+
+    match find_checkpoint(target, key).await? {
+        Some(checkpoint) => {
+            println!("Last created date: {}", checkpoint.last_created_date);
+            println!("Last uidpk: {}", checkpoint.last_uidpk);
+            println!(
+                "Cursor strategy: {}",
+                checkpoint.cursor_strategy.as_deref().unwrap_or("any")
+            );
+            println!("Updated at: {}", checkpoint.updated_date);
+        }
+        None => {
+            println!("No checkpoint stored.");
+            match repo::get_last_raw_created_date(target).await? {
+                Some(date) => println!("Sync will resume from target max created date: {}", date),
+                None => println!("Sync will start from the beginning of source database."),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn checkpoint_set(
+    target: &MySqlPool,
+    key: &CheckpointKey,
+    created_date: &NaiveDateTime,
+    uidpk: u64,
+) -> Result<()> {
+    repo::create_checkpoint_table(target).await?;
+    repo::upsert_checkpoint(target, key, created_date, &BigDecimal::from(uidpk), None).await?;
+    checkpoint_show(target, key).await
+}
+
+async fn checkpoint_reset(target: &MySqlPool, key: &CheckpointKey) -> Result<()> {
+    if repo::checkpoint_table_exists(target).await? && repo::delete_checkpoint(target, key).await? {
+        println!("Checkpoint removed.");
+    } else {
+        println!("There was no checkpoint to remove.");
+    }
+    Ok(())
+}
+
+/// Returns the cursor sync should resume from: the stored checkpoint when there is one,
+/// otherwise the last raw notification on target database according to `strategy`.
+///
+/// A checkpoint saved while paging with another strategy is refused, as its cursor may skip
+/// notifications under `strategy`.
+pub async fn resume_point(
+    pools: &Pools,
+    key: &CheckpointKey,
+    strategy: CursorStrategy,
+) -> Result<Cursor> {
+    if let Some(checkpoint) = find_checkpoint(&pools.target, key).await? {
+        if let Some(saved) = &checkpoint.cursor_strategy {
+            if saved != strategy.name() {
+                bail!(
+                    "The checkpoint of client id {} was saved with the {} cursor strategy, not {}. \
+                    Sync again with --cursor {}, or move the checkpoint with `database checkpoint set`",
+                    key.client_id,
+                    saved,
+                    strategy.name(),
+                    saved
+                );
+            }
+        }
+        return Ok(Cursor::new(
+            checkpoint.last_created_date,
+            checkpoint.last_uidpk,
+        ));
+    }
+
+    Ok(repo::get_last_raw_cursor(&pools.target, strategy)
+        .await
+        .context("Error while fetching target last raw cursor")?
+        .unwrap_or_default())
+}
+
+/// Reads the checkpoint of `key`, without creating the checkpoint table when it is missing.
+async fn find_checkpoint(
+    target: &MySqlPool,
+    key: &CheckpointKey,
+) -> Result<Option<SyncCheckpoint>> {
+    if !repo::checkpoint_table_exists(target).await? {
+        return Ok(None);
+    }
+    repo::find_checkpoint(target, key).await
+}
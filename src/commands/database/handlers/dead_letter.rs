@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+const DEAD_LETTER_FILE_NAME: &str = "dead-letters.jsonl";
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    guid: &'a str,
+    error: String,
+    time: NaiveDateTime,
+}
+
+/// Append-only JSON lines file of notifications that could not be written to target database.
+pub struct DeadLetters {
+    path: PathBuf,
+    file: Mutex<File>,
+    count: AtomicUsize,
+}
+
+impl DeadLetters {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Error while opening dead letter file: {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    /// Dead letter file kept next to the configuration file.
+    pub fn default_path(config_file: &Path) -> PathBuf {
+        config_file.with_file_name(DEAD_LETTER_FILE_NAME)
+    }
+
+    pub fn record(&self, guid: &str, error: &anyhow::Error) -> Result<()> {
+        let line = serde_json::to_string(&DeadLetter {
+            guid,
+            error: format!("{:#}", error),
+            time: Utc::now().naive_utc(),
+        })?;
+
+        let mut file = self.file.lock().expect("dead letter file lock poisoned");
+        writeln!(file, "{}", line)
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Error while writing dead letter for guid {}", guid))?;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fails with `DeadLettered` when any notification was recorded.
+    pub fn check(&self) -> Result<()> {
+        match self.count() {
+            0 => Ok(()),
+            count => Err(DeadLettered {
+                count,
+                path: self.path.clone(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Returned by a run that completed but dead-lettered some notifications.
+#[derive(Debug)]
+pub struct DeadLettered {
+    pub count: usize,
+    pub path: PathBuf,
+}
+
+impl DeadLettered {
+    /// Process exit code of a run that dead-lettered notifications.
+    pub const EXIT_CODE: i32 = 3;
+}
+
+impl Display for DeadLettered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} notifications were dead-lettered, see {}",
+            self.count,
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for DeadLettered {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_record() {
+        let path = std::env::temp_dir().join(format!("dead-letters-{}.jsonl", std::process::id()));
+        let dead_letters = DeadLetters::open(&path).unwrap();
+        assert!(dead_letters.check().is_ok());
+
+        dead_letters
+            .record(
+                "guid-1",
+                &anyhow!("duplicate entry").context("insert failed"),
+            )
+            .unwrap();
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(lines.trim()).unwrap();
+        assert_eq!(line["guid"], "guid-1");
+        assert_eq!(line["error"], "insert failed: duplicate entry");
+        assert!(dead_letters
+            .check()
+            .unwrap_err()
+            .downcast_ref::<DeadLettered>()
+            .is_some());
+    }
+}
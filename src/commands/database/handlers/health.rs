@@ -0,0 +1,275 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use log::{debug, info};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::metrics::Metrics;
+use crate::database::{
+    cursor::Cursor,
+    repo::{self, Pools},
+};
+
+/// Longest request head read before the connection is dropped.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Progress of the watch loop, as reported by the health endpoints.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct HealthState {
+    pub last_heartbeat: Option<NaiveDateTime>,
+    pub last_success: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub cursor_created_date: Option<NaiveDateTime>,
+    pub cursor_uidpk: Option<String>,
+    /// Seconds between the newest source notification and the cursor
+    pub lag_seconds: Option<i64>,
+}
+
+/// Shared between the watch loop, which records its progress, and the HTTP server.
+#[derive(Debug)]
+pub struct Health {
+    state: Mutex<HealthState>,
+    stale_after: Duration,
+}
+
+impl Health {
+    /// The loop is considered stuck once its last heartbeat is older than `stale_after`.
+    pub fn new(stale_after: Duration) -> Self {
+        Self {
+            state: Mutex::default(),
+            stale_after,
+        }
+    }
+
+    /// Records that the loop is alive.
+    pub fn heartbeat(&self) {
+        self.update(|state| state.last_heartbeat = Some(Utc::now().naive_utc()));
+    }
+
+    /// Records a successful poll that left the cursor at `cursor`, `lag` behind source.
+    pub fn success(&self, cursor: &Cursor, lag: Option<chrono::Duration>) {
+        self.update(|state| {
+            let now = Utc::now().naive_utc();
+            state.last_heartbeat = Some(now);
+            state.last_success = Some(now);
+            state.last_error = None;
+            state.cursor_created_date = Some(cursor.created_date);
+            state.cursor_uidpk = Some(cursor.uidpk.to_string());
+            state.lag_seconds = lag.map(|lag| lag.num_seconds().max(0));
+        });
+    }
+
+    pub fn failure(&self, error: &anyhow::Error) {
+        self.update(|state| {
+            state.last_heartbeat = Some(Utc::now().naive_utc());
+            state.last_error = Some(format!("{:#}", error));
+        });
+    }
+
+    pub fn snapshot(&self) -> HealthState {
+        self.state.lock().expect("health lock poisoned").clone()
+    }
+
+    /// Whether the loop sent a heartbeat recently enough.
+    pub fn is_alive(&self) -> bool {
+        let stale_after = chrono::Duration::from_std(self.stale_after)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        self.snapshot()
+            .last_heartbeat
+            .map(|beat| Utc::now().naive_utc().signed_duration_since(beat) <= stale_after)
+            .unwrap_or_default()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut HealthState)) {
+        change(&mut self.state.lock().expect("health lock poisoned"));
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Probe {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(flatten)]
+    state: HealthState,
+}
+
+/// Serves `/healthz`, which checks the watch loop is alive, `/readyz`, which also checks
+/// source and target databases answer, and `/metrics` when metrics are attached.
+pub struct HealthServer {
+    health: Arc<Health>,
+    pools: Pools,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl HealthServer {
+    pub fn new(health: &Arc<Health>, pools: &Pools) -> Self {
+        Self {
+            health: health.clone(),
+            pools: pools.clone(),
+            metrics: None,
+        }
+    }
+
+    /// Serves `metrics` in Prometheus text format on `/metrics`.
+    pub fn with_metrics(mut self, metrics: &Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    pub async fn bind(self, address: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Error while listening on {}", address))?;
+        info!("Health endpoints listening on {}", address);
+        self.serve(listener).await
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, peer) = listener
+                .accept()
+                .await
+                .context("Error while accepting connection")?;
+            let server = server.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(stream).await {
+                    debug!("Health request from {} failed: {:#}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let path = read_path(&mut stream).await?;
+        let (status, content_type, body) = match (path.as_deref(), &self.metrics) {
+            (Some("/healthz"), _) => json(self.healthz())?,
+            (Some("/readyz"), _) => json(self.readyz().await)?,
+            (Some("/metrics"), Some(metrics)) => (
+                "200 OK",
+                "text/plain; version=0.0.4",
+                metrics.render(&self.pools),
+            ),
+            (Some(_), _) => ("404 Not Found", "text/plain", String::new()),
+            (None, _) => ("400 Bad Request", "text/plain", String::new()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    fn healthz(&self) -> (&'static str, Probe) {
+        let alive = self.health.is_alive();
+        let probe = Probe {
+            status: if alive { "ok" } else { "stale" },
+            source: None,
+            target: None,
+            state: self.health.snapshot(),
+        };
+        (status_line(alive), probe)
+    }
+
+    async fn readyz(&self) -> (&'static str, Probe) {
+        let source = check(repo::test_conn(&self.pools.source).await);
+        let target = check(repo::test_conn(&self.pools.target).await);
+        let ready = source == "ok" && target == "ok";
+        let probe = Probe {
+            status: if ready { "ok" } else { "unavailable" },
+            source: Some(source),
+            target: Some(target),
+            state: self.health.snapshot(),
+        };
+        (status_line(ready), probe)
+    }
+}
+
+fn json((status, probe): (&'static str, Probe)) -> Result<(&'static str, &'static str, String)> {
+    Ok((status, "application/json", serde_json::to_string(&probe)?))
+}
+
+fn status_line(ok: bool) -> &'static str {
+    if ok {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    }
+}
+
+fn check(result: Result<()>) -> String {
+    match result {
+        Ok(()) => "ok".to_owned(),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+/// Reads the request head and returns the path of a GET request.
+async fn read_path(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_BYTES {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    Ok(match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Some(target.split('?').next().unwrap_or(target).to_owned()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let pools = Pools::lazy();
+        let health = Arc::new(Health::new(Duration::from_secs(60)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(HealthServer::new(&health, &pools).serve(listener));
+
+        assert!(get(address, "/healthz").await.starts_with("HTTP/1.1 503"));
+
+        health.success(&Cursor::default(), Some(chrono::Duration::seconds(42)));
+        let response = get(address, "/healthz").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\"lag_seconds\":42"));
+
+        assert!(get(address, "/nope").await.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -1,15 +1,195 @@
-use crate::database::{
-    models::{NotificationItem, RawNotification, ToTarget},
-    repo::{self, Pools},
+use super::{
+    dead_letter::DeadLetters,
+    metrics::Metrics,
+    progress::Progress,
+    shutdown::{Interrupted, Shutdown},
 };
-use anyhow::{Ok, Result};
-use log::warn;
-use sqlx::{MySql, MySqlPool, Transaction};
+use crate::{
+    database::{
+        bulk::{BulkLimits, MAX_IN_VALUES},
+        cursor::{Cursor, CursorStrategy},
+        models::{
+            CheckpointKey, NotificationItem, NotificationItemData, NotificationItemOperation,
+            NotificationTree, RawNotification, RawNotificationHeader, ToTarget,
+        },
+        repo::{self, Pools},
+        retry::{self, RetryPolicy},
+        throttle::Throttle,
+    },
+    logging,
+};
+use anyhow::{bail, Ok, Result};
+use clap::ValueEnum;
+use log::{debug, warn};
+use serde::Serialize;
+use sqlx::{Acquire, MySql, MySqlConnection, Transaction};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    ops::AddAssign,
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::{mpsc, watch, Mutex};
+
+/// What to do when a notification already exists on target database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Leave the target notification untouched
+    #[default]
+    Skip,
+
+    /// Replace the whole target tree with the source one
+    Overwrite,
+
+    /// Abort the batch
+    Fail,
+
+    /// Insert only the child rows missing on target
+    FillMissing,
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        f.write_str(value.get_name())
+    }
+}
+
+/// What happened to a single guid during an import.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Inserted,
+    Skipped,
+    Overwritten,
+    /// Conflict resolved with `fill-missing`, holding the number of child rows inserted
+    Filled(usize),
+    NotFound,
+    /// Writing failed, holding the error recorded in the dead letter file
+    DeadLettered(String),
+}
+
+impl ImportOutcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImportOutcome::Inserted => "inserted",
+            ImportOutcome::Skipped => "skipped",
+            ImportOutcome::Overwritten => "overwritten",
+            ImportOutcome::Filled(_) => "filled",
+            ImportOutcome::NotFound => "not found",
+            ImportOutcome::DeadLettered(_) => "dead-lettered",
+        }
+    }
+}
+
+/// Result of importing a list of guids.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Source raw notifications that were found, used to advance the cursor
+    pub raws: Vec<RawNotification>,
+    pub outcomes: Vec<(String, ImportOutcome)>,
+}
+
+impl ImportReport {
+    fn extend(&mut self, other: ImportReport) {
+        self.raws.extend(other.raws);
+        self.outcomes.extend(other.outcomes);
+    }
+}
+
+/// Outcomes accumulated over a whole run.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    policy: ConflictPolicy,
+    counts: BTreeMap<&'static str, usize>,
+    conflicts: Vec<(String, ImportOutcome)>,
+}
+
+impl ImportSummary {
+    pub fn new(policy: ConflictPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, report: &ImportReport) {
+        for (guid, outcome) in &report.outcomes {
+            *self.counts.entry(outcome.label()).or_default() += 1;
+            if *outcome != ImportOutcome::Inserted {
+                self.conflicts.push((guid.clone(), outcome.clone()));
+            }
+        }
+    }
+
+    /// Prints the count of each outcome, and the guids that were not inserted when `verbose`.
+    pub fn print(&self, verbose: bool) {
+        println!("Conflict policy: {}", self.policy);
+        for (label, count) in &self.counts {
+            println!("  {}: {}", label, count);
+        }
+        if !verbose {
+            if !self.conflicts.is_empty() {
+                println!(
+                    "  Run with -v to list the {} notifications that were not inserted",
+                    self.conflicts.len()
+                );
+            }
+            return;
+        }
+        for (guid, outcome) in &self.conflicts {
+            match outcome {
+                ImportOutcome::Filled(rows) => {
+                    println!("  {} -> {} ({} rows)", guid, outcome.label(), rows)
+                }
+                ImportOutcome::DeadLettered(error) => {
+                    println!("  {} -> {} ({})", guid, outcome.label(), error)
+                }
+                _ => println!("  {} -> {}", guid, outcome.label()),
+            }
+        }
+    }
+}
+
+/// Number of rows per table.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RowCounts {
+    pub raws: usize,
+    pub headers: usize,
+    pub items: usize,
+    pub data: usize,
+    pub operations: usize,
+}
+
+impl RowCounts {
+    pub fn total(&self) -> usize {
+        self.raws + self.headers + self.items + self.data + self.operations
+    }
+}
+
+impl AddAssign for RowCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.raws += other.raws;
+        self.headers += other.headers;
+        self.items += other.items;
+        self.data += other.data;
+        self.operations += other.operations;
+    }
+}
 
 pub struct Import<'a> {
     pools: &'a Pools,
     client_id: String,
     threads: u8,
+    checkpoint: Option<(CheckpointKey, CursorStrategy)>,
+    policy: ConflictPolicy,
+    commit_every: Option<usize>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    retry: RetryPolicy,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
+    metrics: Arc<Metrics>,
+    progress: Arc<Progress>,
 }
 
 impl<'a> Import<'a> {
@@ -18,118 +198,878 @@ impl<'a> Import<'a> {
             pools,
             client_id: client_id.to_owned(),
             threads,
+            checkpoint: None,
+            policy: ConflictPolicy::default(),
+            commit_every: None,
+            dead_letters: None,
+            retry: RetryPolicy::default(),
+            throttle: Arc::default(),
+            shutdown: Shutdown::default(),
+            metrics: Arc::default(),
+            progress: Arc::default(),
         }
     }
 
-    pub async fn execute(&self, guids: &Vec<String>) -> Result<Vec<RawNotification>> {
-        let mut join_handlers = vec![];
-        let chunck_size = guids.len() / self.threads as usize;
-        let split = guids
-            .chunks(chunck_size)
-            .map(|c| c.to_owned())
-            .collect::<Vec<_>>();
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Saves the cursor under `key` in the transaction of the last commit of each batch, once all
+    /// the earlier batches are committed.
+    pub fn with_checkpoint(mut self, key: &CheckpointKey, strategy: CursorStrategy) -> Self {
+        self.checkpoint = Some((key.clone(), strategy));
+        self
+    }
+
+    /// Commits every `notifications` notifications instead of once per batch.
+    pub fn with_commit_every(mut self, notifications: Option<usize>) -> Self {
+        self.commit_every = notifications.filter(|n| *n > 0);
+        self
+    }
+
+    /// Writes each notification tree in its own savepoint. A tree that fails is rolled back on
+    /// its own and recorded in `dead_letters` instead of failing the batch.
+    pub fn with_dead_letters(mut self, dead_letters: &Arc<DeadLetters>) -> Self {
+        self.dead_letters = Some(dead_letters.clone());
+        self
+    }
+
+    /// Retries the uncommitted part of a batch failing with a transient database error according
+    /// to `retry`.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Paces source reads with `throttle`, shared with the other readers of source database.
+    pub fn with_throttle(mut self, throttle: &Arc<Throttle>) -> Self {
+        self.throttle = throttle.clone();
+        self
+    }
+
+    /// Stops starting batches once `shutdown` is requested, and rolls back those still in
+    /// flight when its grace period is over, failing with `Interrupted`.
+    pub fn with_shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.shutdown = shutdown.clone();
+        self
+    }
+
+    /// Accounts for committed rows, batches and errors in `metrics`.
+    pub fn with_metrics(mut self, metrics: &Arc<Metrics>) -> Self {
+        self.metrics = metrics.clone();
+        self
+    }
 
-        println!("tamanho: {}", &split.len());
+    /// Shows the guid each worker is importing on `progress`.
+    pub fn with_progress(mut self, progress: &Arc<Progress>) -> Self {
+        self.progress = progress.clone();
+        self
+    }
 
-        for (ix, guids) in split.into_iter().enumerate() {
-            let client_id = self.client_id.clone();
-            let pools = self.pools.clone();
-            let h = tokio::spawn(async move { import(pools, guids, &client_id, ix).await });
-            join_handlers.push(h);
+    /// Plans the import of `guids` exactly as `execute` would, without writing anything.
+    /// Returns the report and the rows that would be inserted.
+    pub async fn dry_run(&self, guids: &[String]) -> Result<(ImportReport, RowCounts)> {
+        let mut source = self.pools.source.acquire().await?;
+        let mut target = self.pools.target.acquire().await?;
+        let plan = plan(
+            &mut source,
+            &mut target,
+            guids.to_vec(),
+            &self.client_id,
+            self.policy,
+            &self.throttle,
+            |_| {},
+        )
+        .await?;
+
+        let mut counts = RowCounts::default();
+        for write in &plan.writes {
+            counts += write.rows.counts();
         }
+        Ok((plan.report, counts))
+    }
 
-        let mut output = vec![];
+    /// Imports `guids`, split evenly between the workers in batches of at most `MAX_IN_VALUES`.
+    pub async fn execute(&self, guids: &[String]) -> Result<ImportReport> {
+        let units = guids
+            .chunks(guids.len().div_ceil(self.workers()).clamp(1, MAX_IN_VALUES))
+            .map(<[String]>::to_vec)
+            .collect::<Vec<_>>();
 
-        for h in join_handlers {
-            output.extend(h.await?.unwrap());
+        let (sender, batches) = mpsc::channel(units.len().max(1));
+        for unit in units {
+            sender.send(unit).await?;
         }
+        drop(sender);
 
+        let mut output = ImportReport::default();
+        self.run(batches, |report| output.extend(report)).await?;
         Ok(output)
     }
+
+    /// Imports every batch received from `batches` until the channel is closed, with `threads`
+    /// workers pulling from it. Each worker commits the batches it imports. `on_committed` is
+    /// called in the order the batches were received and only once every earlier batch is
+    /// committed.
+    pub async fn run(
+        &self,
+        batches: mpsc::Receiver<Vec<String>>,
+        mut on_committed: impl FnMut(ImportReport),
+    ) -> Result<()> {
+        let limits = repo::bulk_limits(&self.pools.target).await?;
+        let options = Arc::new(WorkerOptions {
+            client_id: self.client_id.clone(),
+            limits,
+            policy: self.policy,
+            commit_every: self.commit_every,
+            dead_letters: self.dead_letters.clone(),
+            retry: self.retry,
+            throttle: self.throttle.clone(),
+            shutdown: self.shutdown.clone(),
+            metrics: self.metrics.clone(),
+            progress: self.progress.clone(),
+            checkpoint: self
+                .checkpoint
+                .as_ref()
+                .map(|(key, strategy)| Checkpoint::new(key, *strategy)),
+        });
+        let batches = Arc::new(Mutex::new(Sequenced {
+            receiver: batches,
+            next: 0,
+        }));
+
+        let (results, mut committed) = mpsc::channel(self.workers());
+        let workers = (0..self.workers())
+            .map(|ix| {
+                tokio::spawn(worker(
+                    ix,
+                    self.pools.clone(),
+                    batches.clone(),
+                    options.clone(),
+                    results.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        drop(results);
+
+        let ordered = self.in_order(&mut committed, &mut on_committed);
+        let expired = tokio::select! {
+            result = ordered => {
+                if let Err(e) = result {
+                    workers.iter().for_each(|worker| worker.abort());
+                    return Err(e);
+                }
+                false
+            }
+            _ = self.shutdown.expired() => true,
+        };
+        if expired {
+            warn!("Batches still in flight after the grace period are rolled back");
+            workers.iter().for_each(|worker| worker.abort());
+            return Err(Interrupted.into());
+        }
+        for worker in workers {
+            worker.await?;
+        }
+        Ok(())
+    }
+
+    fn workers(&self) -> usize {
+        (self.threads as usize).max(1)
+    }
+
+    /// Hands the reports of committed batches to `on_committed` in sequence, holding back those
+    /// that were committed ahead of an earlier batch.
+    async fn in_order(
+        &self,
+        committed: &mut mpsc::Receiver<Result<(usize, ImportReport)>>,
+        on_committed: &mut impl FnMut(ImportReport),
+    ) -> Result<()> {
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+
+        while let Some(result) = committed.recv().await {
+            let (seq, report) = result?;
+            pending.insert(seq, report);
+
+            while let Some(report) = pending.remove(&next) {
+                next += 1;
+                on_committed(report);
+            }
+        }
+
+        Ok(())
+    }
 }
 
-async fn import(
-    pools: Pools,
-    guids: Vec<String>,
-    client_id: &str,
-    ix: usize,
-) -> Result<Vec<RawNotification>> {
-    let mut imported_raws = vec![];
-    repo::set_isolation_level(&pools.target).await?;
-    repo::set_isolation_level(&pools.source).await?;
-    let mut tx = pools.target.begin().await?;
-    for guid in guids {
-        println!("ix: {} -> Importanto guid: {}", ix, guid);
-        if let Some(mut raw) = repo::find_raw_by_guid(&pools.source, &guid).await? {
-            if repo::find_raw_by_guid(&mut tx, &guid).await?.is_none() {
-                raw.to_target(client_id);
-                repo::insert_raw_notification(&mut *tx, &raw).await?;
-                fetch_and_insert_headers(&mut tx, &pools.source, &raw).await?;
-                fetch_and_insert_items(&mut tx, &pools.source, &raw).await?;
-            } else {
-                warn!(
-                    "The RawNotification with guid {} already exists on target database",
-                    &guid
-                );
+/// Settings shared by the workers of an import.
+struct WorkerOptions {
+    client_id: String,
+    limits: BulkLimits,
+    policy: ConflictPolicy,
+    commit_every: Option<usize>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    retry: RetryPolicy,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
+    metrics: Arc<Metrics>,
+    progress: Arc<Progress>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Saves the checkpoint of each batch in the transaction of its last commit, taking turns in the
+/// order the batches were received so the checkpoint never gets ahead of an uncommitted batch.
+struct Checkpoint {
+    key: CheckpointKey,
+    strategy: CursorStrategy,
+    /// Sequence of the batch whose turn it is, and the cursor reached by the earlier ones
+    turn: watch::Sender<(usize, Cursor)>,
+}
+
+impl Checkpoint {
+    fn new(key: &CheckpointKey, strategy: CursorStrategy) -> Self {
+        let (turn, _) = watch::channel((0, Cursor::default()));
+        Self {
+            key: key.clone(),
+            strategy,
+            turn,
+        }
+    }
+
+    /// Waits until every batch before `seq` is committed, returning the cursor they reached.
+    async fn turn(&self, seq: usize) -> Cursor {
+        let mut turn = self.turn.subscribe();
+        loop {
+            {
+                let (next, cursor) = &*turn.borrow_and_update();
+                if *next == seq {
+                    return cursor.clone();
+                }
             }
-            imported_raws.push(raw);
-        } else {
-            warn!("RawNotification with guid {} not found", &guid);
+            // The sender lives as long as `self`, so the channel is never closed.
+            let _ = turn.changed().await;
+        }
+    }
+
+    /// Saves in `tx` the cursor `reached` by the earlier batches, as returned by `turn`, advanced
+    /// past `raws`. Returns the cursor to hand over with `done` after commit.
+    async fn save(
+        &self,
+        tx: &mut MySqlConnection,
+        reached: Cursor,
+        raws: &[RawNotification],
+    ) -> Result<Cursor> {
+        let cursor = reached
+            .clone()
+            .advance(self.strategy, raws.iter().map(Cursor::from));
+        if cursor != reached {
+            repo::upsert_checkpoint(
+                tx,
+                &self.key,
+                &cursor.created_date,
+                &cursor.uidpk,
+                Some(self.strategy),
+            )
+            .await?;
+        }
+        Ok(cursor)
+    }
+
+    /// Hands the turn to the batch after `seq`, now committed up to `cursor`.
+    fn done(&self, seq: usize, cursor: Cursor) {
+        self.turn.send_replace((seq + 1, cursor));
+    }
+}
+
+/// Numbers batches in the order they are received, so they can be acknowledged in that order
+/// whichever worker finishes first.
+struct Sequenced {
+    receiver: mpsc::Receiver<Vec<String>>,
+    next: usize,
+}
+
+impl Sequenced {
+    async fn recv(&mut self) -> Option<(usize, Vec<String>)> {
+        let batch = self.receiver.recv().await?;
+        let seq = self.next;
+        self.next += 1;
+        Some((seq, batch))
+    }
+}
+
+/// Imports batches until there are none left, one of them fails or a stop is requested.
+async fn worker(
+    ix: usize,
+    pools: Pools,
+    batches: Arc<Mutex<Sequenced>>,
+    options: Arc<WorkerOptions>,
+    results: mpsc::Sender<Result<(usize, ImportReport)>>,
+) {
+    loop {
+        let next = tokio::select! {
+            next = async { batches.lock().await.recv().await } => next,
+            _ = options.shutdown.requested() => None,
+        };
+        let Some((seq, guids)) = next else {
+            break;
+        };
+        let batch = async {
+            debug!(
+                "worker {} -> importing batch {} of {} guids",
+                ix,
+                seq,
+                guids.len()
+            );
+            let started = Instant::now();
+            import(ix, seq, &pools, &guids, &options)
+                .await
+                .map(|report| {
+                    options.metrics.batch(started.elapsed(), &report);
+                    (seq, report)
+                })
+        };
+        let batch = logging::in_fields(&[("worker", &ix), ("batch", &seq)], batch);
+        let result = batch.await;
+        let failed = result.is_err();
+        if results.send(result).await.is_err() || failed {
+            break;
         }
     }
+}
+
+/// Imports batch `seq` for worker `ix`, in groups of `commit_every` notifications that are each
+/// committed on their own. A group failing with a transient error is retried alone, so the
+/// groups already committed are neither written nor reported twice. The checkpoint is saved with
+/// the last group.
+async fn import(
+    ix: usize,
+    seq: usize,
+    pools: &Pools,
+    guids: &[String],
+    options: &WorkerOptions,
+) -> Result<ImportReport> {
+    let group_size = options.commit_every.unwrap_or(guids.len()).max(1);
+    let groups = guids.chunks(group_size).collect::<Vec<_>>();
+    let mut report = ImportReport::default();
+    for (i, group) in groups.iter().enumerate() {
+        let last = (i + 1 == groups.len()).then_some((seq, report.raws.as_slice()));
+        let committed = options
+            .retry
+            .retry("Importing a batch", || async {
+                let imported = import_group(ix, pools, group, last, options).await;
+                if let Err(e) = &imported {
+                    options.metrics.error(e);
+                }
+                imported
+            })
+            .await?;
+        report.extend(committed);
+    }
+    Ok(report)
+}
+
+/// Imports a group of guids for worker `ix` in a single transaction. When it is the `last` group
+/// of a batch, holding the batch sequence and the notifications of its earlier groups, the
+/// checkpoint is saved in the same transaction.
+async fn import_group(
+    ix: usize,
+    pools: &Pools,
+    guids: &[String],
+    last: Option<(usize, &[RawNotification])>,
+    options: &WorkerOptions,
+) -> Result<ImportReport> {
+    let mut source = pools.source.acquire().await?;
+    let mut target = pools.target.acquire().await?;
+
+    let mut plan = plan(
+        &mut source,
+        &mut target,
+        guids.to_vec(),
+        &options.client_id,
+        options.policy,
+        &options.throttle,
+        |guid| options.progress.current(ix, guid),
+    )
+    .await?;
+    drop(source);
+    drop(target);
+
+    // The turn is awaited before the transaction opens, so a batch waiting on earlier ones holds
+    // neither a connection nor locks.
+    let turn = match (&options.checkpoint, last) {
+        (Some(checkpoint), Some((seq, earlier))) => {
+            Some((checkpoint, seq, earlier, checkpoint.turn(seq).await))
+        }
+        _ => None,
+    };
+
+    let mut conn = pools.target.acquire().await?;
+    repo::set_isolation_level(&mut *conn).await?;
+    let mut tx = conn.begin().await?;
+    let mut failed = write_isolated(&mut tx, &plan.writes, options).await?;
+    let saved = match turn {
+        Some((checkpoint, seq, earlier, reached)) => {
+            let raws = [earlier, plan.report.raws.as_slice()].concat();
+            let cursor = checkpoint.save(&mut tx, reached, &raws).await?;
+            Some((checkpoint, seq, cursor))
+        }
+        None => None,
+    };
     tx.commit().await?;
-    println!("Commitou");
+    if let Some((checkpoint, seq, cursor)) = saved {
+        checkpoint.done(seq, cursor);
+    }
+
+    // Dead letters are only recorded once the group is committed, so a retried group does not
+    // record them twice.
+    if let Some(dead_letters) = &options.dead_letters {
+        for (guid, e) in &failed {
+            logging::with_fields(&[("guid", guid)], || {
+                warn!("Notification {} dead-lettered: {:#}", guid, e)
+            });
+            dead_letters.record(guid, e)?;
+        }
+    }
+
+    let mut rows = RowCounts::default();
+    for write in plan.writes.iter().filter(|w| !failed.contains_key(&w.guid)) {
+        rows += write.rows.counts();
+    }
+    options.metrics.rows_inserted(rows);
+    options.progress.rows(rows.total());
 
-    Ok(imported_raws)
+    for (guid, outcome) in plan.report.outcomes.iter_mut() {
+        if let Some(error) = failed.remove(guid) {
+            *outcome = ImportOutcome::DeadLettered(format!("{:#}", error));
+        }
+    }
+
+    Ok(plan.report)
 }
 
-async fn fetch_and_insert_headers(
+/// Writes `writes` in a savepoint. When dead letters are enabled and that fails, every tree is
+/// retried in a savepoint of its own. Returns the guids of the trees that failed with their
+/// error, to be dead-lettered once the transaction is committed.
+async fn write_isolated(
     tx: &mut Transaction<'_, MySql>,
-    source_pool: &MySqlPool,
-    raw: &RawNotification,
-) -> Result<()> {
-    let headers = repo::find_headers(source_pool, &raw.uidpk).await?;
-    for header in headers {
-        repo::insert_raw_notification_header(&mut *tx, &header).await?;
+    writes: &[TreeWrite],
+    options: &WorkerOptions,
+) -> Result<HashMap<String, anyhow::Error>> {
+    let mut failed = HashMap::new();
+    if options.dead_letters.is_none() {
+        write(tx, writes, &options.limits).await?;
+        return Ok(failed);
     }
-    Ok(())
+
+    let mut savepoint = tx.begin().await?;
+    match write(&mut savepoint, writes, &options.limits).await {
+        Result::Ok(()) => {
+            savepoint.commit().await?;
+            return Ok(failed);
+        }
+        Err(e) if retry::is_transient(&e) => return Err(e),
+        Err(e) => {
+            savepoint.rollback().await?;
+            if writes.len() > 1 {
+                debug!("Batch write failed, isolating each notification: {:#}", e);
+            }
+        }
+    }
+
+    for tree in writes {
+        let mut savepoint = tx.begin().await?;
+        match write(&mut savepoint, std::slice::from_ref(tree), &options.limits).await {
+            Result::Ok(()) => savepoint.commit().await?,
+            // A deadlock or a lost connection aborts the whole transaction, not just the
+            // savepoint, so it is left for the batch to be retried.
+            Err(e) if retry::is_transient(&e) => return Err(e),
+            Err(e) => {
+                savepoint.rollback().await?;
+                failed.insert(tree.guid.clone(), e);
+            }
+        }
+    }
+
+    Ok(failed)
 }
 
-async fn fetch_and_insert_items(
-    tx: &mut Transaction<'_, MySql>,
-    source_pool: &MySqlPool,
-    raw: &RawNotification,
+/// Writes the rows of `writes` with multi-row INSERTs, deleting overwritten trees first.
+async fn write(
+    conn: &mut MySqlConnection,
+    writes: &[TreeWrite],
+    limits: &BulkLimits,
 ) -> Result<()> {
-    let mut items = repo::find_items(source_pool, &raw.guid).await?;
-    for item in items.iter_mut() {
-        item.to_target(&raw.client_id);
-        repo::insert_item(&mut *tx, item).await?;
-        fetch_and_insert_item_data(&mut *tx, source_pool, item).await?;
-        fetch_and_insert_item_operation(&mut *tx, source_pool, item).await?;
+    let overwritten = writes
+        .iter()
+        .filter(|w| w.overwrite)
+        .map(|w| w.guid.clone())
+        .collect::<Vec<_>>();
+    if !overwritten.is_empty() {
+        repo::delete_trees_by_guids(conn, &overwritten).await?;
     }
-    Ok(())
+
+    let mut rows = PendingRows::default();
+    for write in writes {
+        rows.extend(&write.rows);
+    }
+    rows.insert(conn, limits).await
 }
 
-async fn fetch_and_insert_item_data(
-    tx: &mut Transaction<'_, MySql>,
-    source_pool: &MySqlPool,
-    item: &NotificationItem,
-) -> Result<()> {
-    let datas = repo::find_item_data(source_pool, &item.uidpk).await?;
-    for data in datas {
-        repo::insert_item_data(&mut *tx, &data).await?;
+/// What importing a chunk of guids writes on target database.
+struct ChunkPlan {
+    report: ImportReport,
+    writes: Vec<TreeWrite>,
+}
+
+/// Rows written for a single notification.
+struct TreeWrite {
+    guid: String,
+    rows: PendingRows,
+    /// Whether the target tree is deleted before `rows` are inserted
+    overwrite: bool,
+}
+
+impl TreeWrite {
+    fn new(guid: &str, overwrite: bool) -> Self {
+        Self {
+            guid: guid.to_owned(),
+            rows: PendingRows::default(),
+            overwrite,
+        }
     }
-    Ok(())
 }
 
-async fn fetch_and_insert_item_operation(
-    tx: &mut Transaction<'_, MySql>,
-    source_pool: &MySqlPool,
-    item: &NotificationItem,
-) -> Result<()> {
-    let operations = repo::find_item_operations(source_pool, &item.uidpk).await?;
-    for operation in operations {
-        repo::insert_item_operation(&mut *tx, &operation).await?;
+/// Fetches the trees of `guids` from source and decides, against target, what has to be written.
+/// `current` is called with each guid as it is planned.
+async fn plan(
+    source: &mut MySqlConnection,
+    target: &mut MySqlConnection,
+    guids: Vec<String>,
+    client_id: &str,
+    policy: ConflictPolicy,
+    throttle: &Throttle,
+    current: impl Fn(&str),
+) -> Result<ChunkPlan> {
+    let mut report = ImportReport::default();
+    let mut writes = vec![];
+
+    let mut trees = fetch_trees(source, &guids, throttle)
+        .await?
+        .into_iter()
+        .map(|tree| (tree.raw.guid.clone(), tree))
+        .collect::<HashMap<_, _>>();
+    let existing = repo::find_existing_raw_guids(target, &guids)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut target_trees = HashMap::new();
+    if policy == ConflictPolicy::FillMissing && !existing.is_empty() {
+        let existing = existing.iter().cloned().collect::<Vec<_>>();
+        target_trees = repo::find_trees_by_guids(target, &existing)
+            .await?
+            .into_iter()
+            .map(|tree| (tree.raw.guid.clone(), tree))
+            .collect();
+    }
+
+    for guid in guids {
+        current(&guid);
+        logging::with_fields(&[("guid", &guid)], || debug!("importing guid: {}", guid));
+        let Some(mut tree) = trees.remove(&guid) else {
+            logging::with_fields(&[("guid", &guid)], || {
+                warn!("RawNotification with guid {} not found", &guid)
+            });
+            report.outcomes.push((guid, ImportOutcome::NotFound));
+            continue;
+        };
+
+        tree.to_target(client_id);
+        report.raws.push(tree.raw.clone());
+
+        let outcome = if !existing.contains(&guid) {
+            let mut write = TreeWrite::new(&guid, false);
+            write.rows.push(tree);
+            writes.push(write);
+            ImportOutcome::Inserted
+        } else {
+            match policy {
+                ConflictPolicy::Skip => {
+                    logging::with_fields(&[("guid", &guid)], || {
+                        warn!(
+                            "The RawNotification with guid {} already exists on target database",
+                            &guid
+                        )
+                    });
+                    ImportOutcome::Skipped
+                }
+                ConflictPolicy::Fail => bail!(
+                    "The RawNotification with guid {} already exists on target database",
+                    &guid
+                ),
+                ConflictPolicy::Overwrite => {
+                    let mut write = TreeWrite::new(&guid, true);
+                    write.rows.push(tree);
+                    writes.push(write);
+                    ImportOutcome::Overwritten
+                }
+                ConflictPolicy::FillMissing => {
+                    let mut write = TreeWrite::new(&guid, false);
+                    let outcome = match target_trees.get(&guid) {
+                        Some(target) => {
+                            ImportOutcome::Filled(write.rows.push_missing(tree, target))
+                        }
+                        None => {
+                            write.rows.push(tree);
+                            ImportOutcome::Inserted
+                        }
+                    };
+                    writes.push(write);
+                    outcome
+                }
+            }
+        };
+        report.outcomes.push((guid, outcome));
+    }
+
+    Ok(ChunkPlan { report, writes })
+}
+
+/// Fetches the trees of `guids` with set-based queries, falling back to fetching them one by
+/// one when that fails.
+async fn fetch_trees(
+    conn: &mut MySqlConnection,
+    guids: &[String],
+    throttle: &Throttle,
+) -> Result<Vec<NotificationTree>> {
+    throttle
+        .queries(repo::TREE_QUERIES * guids.len().div_ceil(MAX_IN_VALUES))
+        .await;
+    let trees = match repo::find_trees_by_guids(conn, guids).await {
+        Result::Ok(trees) => trees,
+        Err(e) => {
+            warn!(
+                "Set-based fetch of {} guids failed, fetching one by one: {:#}",
+                guids.len(),
+                e
+            );
+            let mut trees = vec![];
+            for guid in guids {
+                throttle.queries(repo::TREE_QUERIES).await;
+                if let Some(tree) = repo::find_tree_by_guid(conn, guid).await? {
+                    trees.push(tree);
+                }
+            }
+            trees
+        }
+    };
+
+    throttle
+        .rows(trees.iter().map(NotificationTree::rows).sum())
+        .await;
+    Ok(trees)
+}
+
+/// Rows of a chunk waiting to be written with multi-row INSERTs.
+#[derive(Default)]
+struct PendingRows {
+    raws: Vec<RawNotification>,
+    headers: Vec<RawNotificationHeader>,
+    items: Vec<NotificationItem>,
+    data: Vec<NotificationItemData>,
+    operations: Vec<NotificationItemOperation>,
+}
+
+impl PendingRows {
+    fn push(&mut self, tree: NotificationTree) {
+        self.raws.push(tree.raw);
+        self.headers.extend(tree.headers);
+        for item in tree.items {
+            self.items.push(item.item);
+            self.data.extend(item.data);
+            self.operations.extend(item.operations);
+        }
+    }
+
+    /// Queues the rows of `tree` that are absent from `target`, the same notification as stored
+    /// on target database. Returns the number of rows queued.
+    fn push_missing(&mut self, tree: NotificationTree, target: &NotificationTree) -> usize {
+        let before = self.len();
+
+        let headers = target
+            .headers
+            .iter()
+            .map(|h| h.name.as_str())
+            .collect::<HashSet<_>>();
+        self.headers.extend(
+            tree.headers
+                .into_iter()
+                .filter(|h| !headers.contains(h.name.as_str())),
+        );
+
+        let items = target
+            .items
+            .iter()
+            .map(|i| (&i.item.uidpk, i))
+            .collect::<HashMap<_, _>>();
+        for item in tree.items {
+            let Some(target_item) = items.get(&item.item.uidpk) else {
+                self.items.push(item.item);
+                self.data.extend(item.data);
+                self.operations.extend(item.operations);
+                continue;
+            };
+
+            let data = target_item
+                .data
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect::<HashSet<_>>();
+            self.data.extend(
+                item.data
+                    .into_iter()
+                    .filter(|d| !data.contains(d.name.as_str())),
+            );
+
+            let operations = target_item
+                .operations
+                .iter()
+                .map(|o| o.operation.as_str())
+                .collect::<HashSet<_>>();
+            self.operations.extend(
+                item.operations
+                    .into_iter()
+                    .filter(|o| !operations.contains(o.operation.as_str())),
+            );
+        }
+
+        self.len() - before
+    }
+
+    fn len(&self) -> usize {
+        self.counts().total()
+    }
+
+    fn counts(&self) -> RowCounts {
+        RowCounts {
+            raws: self.raws.len(),
+            headers: self.headers.len(),
+            items: self.items.len(),
+            data: self.data.len(),
+            operations: self.operations.len(),
+        }
+    }
+
+    fn extend(&mut self, other: &PendingRows) {
+        self.raws.extend(other.raws.iter().cloned());
+        self.headers.extend(other.headers.iter().cloned());
+        self.items.extend(other.items.iter().cloned());
+        self.data.extend(other.data.iter().cloned());
+        self.operations.extend(other.operations.iter().cloned());
+    }
+
+    async fn insert(&self, conn: &mut MySqlConnection, limits: &BulkLimits) -> Result<()> {
+        repo::insert_raw_notifications(conn, &self.raws, limits).await?;
+        repo::insert_raw_notification_headers(conn, &self.headers, limits).await?;
+        repo::insert_items(conn, &self.items, limits).await?;
+        repo::insert_items_data(conn, &self.data, limits).await?;
+        repo::insert_item_operations(conn, &self.operations, limits).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use sqlx::types::BigDecimal;
+
+    fn report(guid: &str) -> ImportReport {
+        ImportReport {
+            raws: vec![],
+            outcomes: vec![(guid.to_owned(), ImportOutcome::Inserted)],
+        }
+    }
+
+    fn cursor(uidpk: usize) -> Cursor {
+        Cursor::new(
+            NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
+            BigDecimal::from(uidpk as u32),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_in_order() {
+        let pools = Pools::lazy();
+        let import = Import::new(&pools, "client", 3);
+        let (sender, mut committed) = mpsc::channel(3);
+        for seq in [2, 0, 1] {
+            sender
+                .send(Ok((seq, report(&seq.to_string()))))
+                .await
+                .unwrap();
+        }
+        drop(sender);
+
+        let mut guids = vec![];
+        import
+            .in_order(&mut committed, &mut |report: ImportReport| {
+                guids.push(report.outcomes[0].0.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(guids, ["0", "1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_in_order_holds_back_after_error() {
+        let pools = Pools::lazy();
+        let import = Import::new(&pools, "client", 2);
+        let (sender, mut committed) = mpsc::channel(3);
+        sender.send(Ok((1, report("1")))).await.unwrap();
+        sender.send(Err(anyhow::anyhow!("failed"))).await.unwrap();
+        drop(sender);
+
+        let mut guids: Vec<String> = vec![];
+        let result = import
+            .in_order(&mut committed, &mut |report: ImportReport| {
+                guids.push(report.outcomes[0].0.clone())
+            })
+            .await;
+        assert!(result.is_err());
+        assert!(guids.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_turns() {
+        let key = CheckpointKey::new("mysql://source", "mysql://target", "client");
+        let checkpoint = Arc::new(Checkpoint::new(&key, CursorStrategy::Uidpk));
+        let (sender, mut saved) = mpsc::channel(3);
+        let tasks = [2, 0, 1]
+            .into_iter()
+            .map(|seq| {
+                let checkpoint = checkpoint.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let reached = checkpoint.turn(seq).await;
+                    sender.send((seq, reached)).await.unwrap();
+                    checkpoint.done(seq, cursor(seq + 1));
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let mut turns = vec![];
+        while let Some(turn) = saved.recv().await {
+            turns.push(turn);
+        }
+        assert_eq!(
+            turns,
+            [(0, Cursor::default()), (1, cursor(1)), (2, cursor(2))]
+        );
+        assert_eq!(checkpoint.turn(3).await, cursor(3));
     }
-    Ok(())
 }
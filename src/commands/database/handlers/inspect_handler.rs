@@ -0,0 +1,312 @@
+use crate::commands::database::commands::{DatabaseInspectArgs, InspectSide, OutputFormat};
+use crate::commands::root::GlobalOpts;
+use crate::database::models::{NotificationItemTree, NotificationTree};
+use crate::database::reconcile::{group, item_fields, raw_fields, value};
+use crate::database::repo::{self, Pools};
+use crate::settings::{MergeSettings, Settings};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sqlx::MySqlPool;
+use std::collections::BTreeSet;
+
+pub async fn database_inspect(
+    settings: &Settings,
+    _: &GlobalOpts,
+    args: DatabaseInspectArgs,
+) -> Result<()> {
+    let args = args.merge(settings);
+    let pools: Pools = Pools::try_from(&args).context("Error creating connection pools.")?;
+
+    let source = match args.side {
+        InspectSide::Source | InspectSide::Both => {
+            fetch(&pools.source, &args.guid, "source").await?
+        }
+        InspectSide::Target => None,
+    };
+    let target = match args.side {
+        InspectSide::Target | InspectSide::Both => {
+            fetch(&pools.target, &args.guid, "target").await?
+        }
+        InspectSide::Source => None,
+    };
+    match (args.side, &source, &target) {
+        (InspectSide::Source, None, _) => {
+            bail!("Notification {} not found on source database", args.guid)
+        }
+        (InspectSide::Target, _, None) => {
+            bail!("Notification {} not found on target database", args.guid)
+        }
+        (InspectSide::Both, None, None) => {
+            bail!("Notification {} not found on either database", args.guid)
+        }
+        _ => {}
+    }
+
+    match (args.side, args.output) {
+        (_, OutputFormat::Json) => {
+            let value = match (args.side, &source, &target) {
+                (InspectSide::Source, Some(tree), _) | (InspectSide::Target, _, Some(tree)) => {
+                    to_json(tree)
+                }
+                _ => json!({
+                    "source": source.as_ref().map(to_json),
+                    "target": target.as_ref().map(to_json),
+                }),
+            };
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        (InspectSide::Both, OutputFormat::Table) => {
+            print_side_by_side(source.as_ref(), target.as_ref())
+        }
+        (_, OutputFormat::Table) => {
+            for tree in source.iter().chain(target.iter()) {
+                print_tree(tree);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn fetch(pool: &MySqlPool, guid: &str, side: &str) -> Result<Option<NotificationTree>> {
+    let mut conn = pool
+        .acquire()
+        .await
+        .with_context(|| format!("Error while connecting to {} database", side))?;
+    repo::find_tree_by_guid(&mut conn, guid)
+        .await
+        .with_context(|| format!("Error while reading notification from {} database", side))
+}
+
+/// Every stored field of `tree` with its path, in tree order. Headers and data rows sharing a
+/// name are grouped under one path, their values joined like verify compares them.
+fn fields(tree: &NotificationTree) -> Vec<(String, String)> {
+    let mut fields = raw_fields(&tree.raw)
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect::<Vec<_>>();
+    let headers = tree
+        .headers
+        .iter()
+        .map(|header| (header.name.clone(), value(&header.value)));
+    for (name, values) in group(headers) {
+        fields.push((format!("headers[{}]", name), values.join(", ")));
+    }
+    for tree in &tree.items {
+        let path = format!("items[{}]", tree.item.guid);
+        for (name, value) in item_fields(&tree.item) {
+            fields.push((format!("{}.{}", path, name), value));
+        }
+        let data = tree
+            .data
+            .iter()
+            .map(|data| (data.name.clone(), value(&data.value)));
+        for (name, values) in group(data) {
+            fields.push((format!("{}.data[{}]", path, name), values.join(", ")));
+        }
+        fields.push((format!("{}.operations", path), operations(tree)));
+    }
+    fields
+}
+
+fn print_tree(tree: &NotificationTree) {
+    println!("RawNotification {}", tree.raw.guid);
+    for (name, value) in raw_fields(&tree.raw) {
+        if name == "body" {
+            println!("  body:");
+            for line in pretty_body(&value).lines() {
+                println!("    {}", line);
+            }
+        } else {
+            println!("  {}: {}", name, value);
+        }
+    }
+    println!("  headers:");
+    for header in &tree.headers {
+        println!("    {}: {}", header.name, value(&header.value));
+    }
+    println!("  items:");
+    for tree in &tree.items {
+        println!("    NotificationItem {}", tree.item.guid);
+        for (name, value) in item_fields(&tree.item) {
+            println!("      {}: {}", name, value);
+        }
+        println!("      data:");
+        for data in &tree.data {
+            println!("        {}: {}", data.name, value(&data.value));
+        }
+        println!("      operations: {}", operations(tree));
+    }
+}
+
+fn operations(tree: &NotificationItemTree) -> String {
+    tree.operations
+        .iter()
+        .map(|operation| operation.operation.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints the fields of both trees aligned by path, marking the ones that differ with `*`.
+fn print_side_by_side(source: Option<&NotificationTree>, target: Option<&NotificationTree>) {
+    let source = source.map(fields).unwrap_or_default();
+    let target = target.map(fields).unwrap_or_default();
+
+    let mut seen = BTreeSet::new();
+    let paths = source
+        .iter()
+        .chain(target.iter())
+        .map(|(path, _)| path.as_str())
+        .filter(|path| seen.insert(*path))
+        .collect::<Vec<_>>();
+    let find = |fields: &[(String, String)], path: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| name == path)
+            .map(|(_, value)| value.clone())
+    };
+
+    let width = paths
+        .iter()
+        .map(|path| path.len())
+        .max()
+        .unwrap_or_default();
+    println!("  {:<width$}  {:<40}  target", "field", "source");
+    for path in paths {
+        let source = find(&source, path);
+        let target = find(&target, path);
+        println!(
+            "{} {:<width$}  {:<40}  {}",
+            if source == target { ' ' } else { '*' },
+            path,
+            cell(source.as_deref()),
+            cell(target.as_deref()),
+        );
+    }
+}
+
+fn cell(value: Option<&str>) -> String {
+    let value = value.unwrap_or("<absent>").replace('\n', " ");
+    if value.chars().count() > 40 {
+        format!("{}...", value.chars().take(37).collect::<String>())
+    } else {
+        value
+    }
+}
+
+fn to_json(tree: &NotificationTree) -> Value {
+    let raw = &tree.raw;
+    json!({
+        "uidpk": raw.uidpk.to_string(),
+        "guid": raw.guid,
+        "created_date": raw.created_date,
+        "consumed": raw.consumed.to_string(),
+        "consumed_date": raw.consumed_date,
+        "consume_success": raw.consume_success.to_string(),
+        "client_id": raw.client_id,
+        "body": raw.body.as_deref().map(|body| {
+            serde_json::from_str::<Value>(body).unwrap_or_else(|_| Value::from(body))
+        }),
+        "headers": tree.headers.iter().map(|header| json!({
+            "name": header.name,
+            "value": header.value,
+        })).collect::<Vec<_>>(),
+        "items": tree.items.iter().map(|tree| {
+            let item = &tree.item;
+            json!({
+                "uidpk": item.uidpk.to_string(),
+                "guid": item.guid,
+                "created_date": item.created_date,
+                "consumed": item.consumed.to_string(),
+                "consumed_date": item.consumed_date,
+                "consume_success": item.consume_success.to_string(),
+                "client_id": item.client_id,
+                "currency": item.currency,
+                "amount": item.amount.as_ref().map(|amount| amount.to_string()),
+                "event_code": item.event_code,
+                "event_date": item.event_date,
+                "merchant_account_code": item.merchant_account_code,
+                "merchant_reference": item.merchant_reference,
+                "payment_method": item.payment_method,
+                "psp_reference": item.psp_reference,
+                "reason": item.reason,
+                "success": item.success.to_string(),
+                "live": item.live.to_string(),
+                "original_reference": item.original_reference,
+                "data": tree.data.iter().map(|data| json!({
+                    "name": data.name,
+                    "value": data.value,
+                })).collect::<Vec<_>>(),
+                "operations": tree.operations.iter().map(|operation| &operation.operation)
+                    .collect::<Vec<_>>(),
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Indents `body` when it is JSON, and leaves it as is otherwise.
+fn pretty_body(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| body.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::{RawNotification, RawNotificationHeader};
+    use chrono::NaiveDateTime;
+    use sqlx::types::BigDecimal;
+
+    fn header(name: &str, value: &str) -> RawNotificationHeader {
+        RawNotificationHeader {
+            tadyen_raw_notification_uid: BigDecimal::from(1),
+            name: name.to_owned(),
+            value: Some(value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_fields_repeated_rows() {
+        let tree = NotificationTree {
+            raw: RawNotification {
+                uidpk: BigDecimal::from(1),
+                guid: "raw".to_owned(),
+                created_date: NaiveDateTime::default(),
+                consumed_date: None,
+                consumed: BigDecimal::from(0),
+                body: None,
+                client_id: "client".to_owned(),
+                consume_success: BigDecimal::from(0),
+            },
+            headers: vec![
+                header("x-id", "b"),
+                header("accept", "*"),
+                header("x-id", "a"),
+            ],
+            items: vec![],
+        };
+
+        let headers = fields(&tree)
+            .into_iter()
+            .filter(|(path, _)| path.starts_with("headers"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            [
+                ("headers[accept]".to_owned(), "*".to_owned()),
+                ("headers[x-id]".to_owned(), "a, b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cell() {
+        assert_eq!(cell(None), "<absent>");
+        assert_eq!(cell(Some("{\n}")), "{ }");
+        let long = "x".repeat(50);
+        assert_eq!(cell(Some(&long)), format!("{}...", "x".repeat(37)));
+        assert_eq!(pretty_body("{\"a\":1}"), "{\n  \"a\": 1\n}");
+        assert_eq!(pretty_body("[accepted]"), "[accepted]");
+    }
+}
@@ -0,0 +1,386 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::import::{ImportReport, RowCounts};
+use crate::database::{repo::Pools, retry};
+
+/// Upper bounds, in seconds, of the batch duration histogram buckets.
+const BATCH_SECONDS_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Counters and gauges of an import, rendered in Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    samples: Mutex<Samples>,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    rows: RowCounts,
+    outcomes: BTreeMap<String, u64>,
+    errors: BTreeMap<&'static str, u64>,
+    batches: u64,
+    batch_seconds: Histogram,
+    lag_seconds: Option<i64>,
+    last_commit: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BATCH_SECONDS_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(ix) = BATCH_SECONDS_BUCKETS.iter().position(|le| value <= *le) {
+            self.buckets[ix] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    /// Accounts for rows committed on target database.
+    pub fn rows_inserted(&self, rows: RowCounts) {
+        self.update(|samples| samples.rows += rows);
+    }
+
+    /// Accounts for a batch committed after `elapsed`, including its retries.
+    pub fn batch(&self, elapsed: Duration, report: &ImportReport) {
+        self.update(|samples| {
+            samples.batches += 1;
+            samples.batch_seconds.observe(elapsed.as_secs_f64());
+            samples.last_commit = Some(unix_seconds());
+            for (_, outcome) in &report.outcomes {
+                let label = outcome.label().replace([' ', '-'], "_");
+                *samples.outcomes.entry(label).or_default() += 1;
+            }
+            let dead_lettered = report
+                .outcomes
+                .iter()
+                .filter(|(_, outcome)| outcome.label() == "dead-lettered")
+                .count();
+            if dead_lettered > 0 {
+                *samples.errors.entry("dead_lettered").or_default() += dead_lettered as u64;
+            }
+        });
+    }
+
+    /// Accounts for a failed attempt at a batch.
+    pub fn error(&self, error: &anyhow::Error) {
+        let kind = if retry::is_transient(error) {
+            "transient"
+        } else {
+            "fatal"
+        };
+        self.update(|samples| *samples.errors.entry(kind).or_default() += 1);
+    }
+
+    /// Sets how far the cursor is behind the newest notification of source database.
+    pub fn set_lag(&self, lag: Option<chrono::Duration>) {
+        self.update(|samples| samples.lag_seconds = lag.map(|lag| lag.num_seconds().max(0)));
+    }
+
+    /// Renders every metric, with the current usage of `pools`, in Prometheus text format.
+    pub fn render(&self, pools: &Pools) -> String {
+        let samples = self.samples.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "rows_inserted_total",
+            "counter",
+            "Rows inserted on target database",
+        );
+        for (table, count) in [
+            ("raws", samples.rows.raws),
+            ("headers", samples.rows.headers),
+            ("items", samples.rows.items),
+            ("data", samples.rows.data),
+            ("operations", samples.rows.operations),
+        ] {
+            sample(&mut out, "rows_inserted_total", &[("table", table)], count);
+        }
+
+        header(
+            &mut out,
+            "notifications_total",
+            "counter",
+            "Notifications imported, by outcome",
+        );
+        for (outcome, count) in &samples.outcomes {
+            sample(
+                &mut out,
+                "notifications_total",
+                &[("outcome", outcome)],
+                count,
+            );
+        }
+
+        header(
+            &mut out,
+            "errors_total",
+            "counter",
+            "Import errors, by kind",
+        );
+        for (kind, count) in &samples.errors {
+            sample(&mut out, "errors_total", &[("kind", kind)], count);
+        }
+
+        header(&mut out, "batches_total", "counter", "Batches committed");
+        sample(&mut out, "batches_total", &[], samples.batches);
+
+        header(
+            &mut out,
+            "batch_duration_seconds",
+            "histogram",
+            "Time to import and commit a batch, retries included",
+        );
+        let histogram = &samples.batch_seconds;
+        let mut cumulative = 0;
+        for (le, count) in BATCH_SECONDS_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let le = le.to_string();
+            sample(
+                &mut out,
+                "batch_duration_seconds_bucket",
+                &[("le", &le)],
+                cumulative,
+            );
+        }
+        sample(
+            &mut out,
+            "batch_duration_seconds_bucket",
+            &[("le", "+Inf")],
+            histogram.count,
+        );
+        sample(&mut out, "batch_duration_seconds_sum", &[], histogram.sum);
+        sample(
+            &mut out,
+            "batch_duration_seconds_count",
+            &[],
+            histogram.count,
+        );
+
+        if let Some(lag) = samples.lag_seconds {
+            header(
+                &mut out,
+                "lag_seconds",
+                "gauge",
+                "Seconds between the newest source notification and the cursor",
+            );
+            sample(&mut out, "lag_seconds", &[], lag);
+        }
+
+        if let Some(last_commit) = samples.last_commit {
+            header(
+                &mut out,
+                "last_commit_timestamp_seconds",
+                "gauge",
+                "Unix time of the last committed batch",
+            );
+            sample(&mut out, "last_commit_timestamp_seconds", &[], last_commit);
+        }
+
+        header(
+            &mut out,
+            "pool_connections",
+            "gauge",
+            "Connections of the pools, by state",
+        );
+        for (name, pool) in [("source", &pools.source), ("target", &pools.target)] {
+            let idle = pool.num_idle() as u32;
+            let size = pool.size();
+            sample(
+                &mut out,
+                "pool_connections",
+                &[("pool", name), ("state", "idle")],
+                idle,
+            );
+            sample(
+                &mut out,
+                "pool_connections",
+                &[("pool", name), ("state", "in_use")],
+                size.saturating_sub(idle),
+            );
+        }
+
+        out
+    }
+
+    /// Pushes every metric to the Prometheus Pushgateway at `gateway`, grouped under `job`
+    /// and `client_id`, replacing what an earlier run pushed there.
+    pub async fn push(
+        &self,
+        gateway: &str,
+        job: &str,
+        client_id: &str,
+        pools: &Pools,
+    ) -> Result<()> {
+        let Some(address) = gateway.strip_prefix("http://") else {
+            bail!("Only http:// push gateways are supported: {}", gateway);
+        };
+        let (host, base) = match address.split_once('/') {
+            Some((host, base)) => (host, format!("/{}", base.trim_end_matches('/'))),
+            None => (address, String::new()),
+        };
+        let path = format!(
+            "{}/metrics/job@base64/{}/client_id@base64/{}",
+            base,
+            label_value(job),
+            label_value(client_id)
+        );
+        let host = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+
+        let body = self.render(pools);
+        let request = format!(
+            "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect(&host)
+            .await
+            .with_context(|| format!("Error while connecting to push gateway {}", host))?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .context("Error while reading push gateway response")?;
+
+        let status = response.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            bail!(
+                "Push gateway answered: {}",
+                response.lines().next().unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Samples)) {
+        change(&mut self.samples.lock().expect("metrics lock poisoned"));
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP adyen_sync_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE adyen_sync_{} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl ToString) {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect::<Vec<_>>();
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    };
+    let _ = writeln!(out, "adyen_sync_{}{} {}", name, labels, value.to_string());
+}
+
+/// Encodes a grouping label value for a Pushgateway URL, so any character may be used.
+fn label_value(value: &str) -> String {
+    match value {
+        "" => "=".to_owned(),
+        value => base64::encode_config(value, base64::URL_SAFE),
+    }
+}
+
+fn unix_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::database::handlers::import::ImportOutcome;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_render() {
+        let pools = Pools::lazy();
+        let metrics = Metrics::default();
+        metrics.rows_inserted(RowCounts {
+            raws: 2,
+            items: 3,
+            ..Default::default()
+        });
+        metrics.batch(
+            Duration::from_millis(300),
+            &ImportReport {
+                raws: vec![],
+                outcomes: vec![
+                    ("a".to_owned(), ImportOutcome::Inserted),
+                    ("b".to_owned(), ImportOutcome::NotFound),
+                ],
+            },
+        );
+        metrics.set_lag(Some(chrono::Duration::seconds(12)));
+
+        let text = metrics.render(&pools);
+        assert!(text.contains("adyen_sync_rows_inserted_total{table=\"items\"} 3\n"));
+        assert!(text.contains("adyen_sync_notifications_total{outcome=\"not_found\"} 1\n"));
+        assert!(text.contains("adyen_sync_batch_duration_seconds_bucket{le=\"0.25\"} 0\n"));
+        assert!(text.contains("adyen_sync_batch_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("adyen_sync_lag_seconds 12\n"));
+        assert!(text.contains("adyen_sync_pool_connections{pool=\"source\",state=\"in_use\"} 0\n"));
+    }
+
+    #[test]
+    fn test_label_value() {
+        assert_eq!(label_value(""), "=");
+        assert_eq!(label_value("a/b"), "YS9i");
+        assert_eq!(label_value("??>"), "Pz8-");
+    }
+
+    #[tokio::test]
+    async fn test_push() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gateway = format!("http://{}", listener.local_addr().unwrap());
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 64 * 1024];
+            let read = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+
+        Metrics::default()
+            .push(&gateway, "adyen_sync", "client", &Pools::lazy())
+            .await
+            .unwrap();
+        let request = received.await.unwrap();
+        assert!(request.starts_with(
+            "PUT /metrics/job@base64/YWR5ZW5fc3luYw==/client_id@base64/Y2xpZW50 HTTP/1.1"
+        ));
+        assert!(request.contains("adyen_sync_batches_total 0"));
+    }
+}
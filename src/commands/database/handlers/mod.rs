@@ -1,13 +1,25 @@
 use self::{
-    status_handler::database_status, sync_handler::databse_sync, watch_handler::database_watch,
+    checkpoint_handler::database_checkpoint, inspect_handler::database_inspect,
+    repair_handler::database_repair, status_handler::database_status, sync_handler::databse_sync,
+    verify_handler::database_verify, watch_handler::database_watch,
 };
 use super::commands::{DatabaseCommand, DatabaseSubCommand};
 use crate::{commands::root::GlobalOpts, settings::Settings};
 use anyhow::Result;
 
+pub mod checkpoint_handler;
+pub mod dead_letter;
+pub mod health;
 pub mod import;
+pub mod inspect_handler;
+pub mod metrics;
+pub mod producer;
+pub mod progress;
+pub mod repair_handler;
+pub mod shutdown;
 pub mod status_handler;
 pub mod sync_handler;
+pub mod verify_handler;
 pub mod watch_handler;
 
 pub async fn database_handler(
@@ -18,6 +30,12 @@ pub async fn database_handler(
     match command.command {
         DatabaseSubCommand::Status { args } => database_status(settings, globals, args).await,
         DatabaseSubCommand::Watch { args } => database_watch(settings, globals, args).await,
-        DatabaseSubCommand::Sync { args } => databse_sync(settings, globals, args).await,
+        DatabaseSubCommand::Sync { args } => databse_sync(settings, globals, *args).await,
+        DatabaseSubCommand::Verify { args } => database_verify(settings, globals, args).await,
+        DatabaseSubCommand::Repair { args } => database_repair(settings, globals, args).await,
+        DatabaseSubCommand::Inspect { args } => database_inspect(settings, globals, args).await,
+        DatabaseSubCommand::Checkpoint { args, command } => {
+            database_checkpoint(settings, globals, args, command).await
+        }
     }
 }
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{debug, warn};
+use tokio::sync::mpsc;
+
+use super::shutdown::Shutdown;
+use crate::database::{
+    cursor::{Cursor, CursorStrategy, ItemFilter, TimeWindow},
+    repo::{self, Pools},
+    retry::{self, RetryPolicy},
+    throttle::Throttle,
+};
+
+/// Pages source database and queues the guids it reads for the import workers.
+///
+/// Pages of `batch_size` keys, which must not exceed `MAX_IN_VALUES`, are read and handed over
+/// as one batch each, so neither the producer nor a worker holds more than a page in memory.
+pub struct Producer {
+    pools: Pools,
+    strategy: CursorStrategy,
+    window: TimeWindow,
+    filter: ItemFilter,
+    batch_size: u64,
+    retry: RetryPolicy,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
+}
+
+impl Producer {
+    pub fn new(pools: &Pools, strategy: CursorStrategy, batch_size: u64) -> Self {
+        Self {
+            pools: pools.clone(),
+            strategy,
+            window: TimeWindow::default(),
+            filter: ItemFilter::default(),
+            batch_size,
+            retry: RetryPolicy::default(),
+            throttle: Arc::default(),
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    pub fn with_window(mut self, window: TimeWindow) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ItemFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_throttle(mut self, throttle: &Arc<Throttle>) -> Self {
+        self.throttle = throttle.clone();
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.shutdown = shutdown.clone();
+        self
+    }
+
+    /// Queues every guid after `cursor` until source database is exhausted, the workers are
+    /// gone or a stop is requested. A page failing with a transient error is read again from the last queued guid.
+    pub async fn run(self, mut cursor: Cursor, batches: mpsc::Sender<Vec<String>>) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            if self.shutdown.is_requested() {
+                return Ok(());
+            }
+            match self.queue_page(&mut cursor, &batches).await {
+                Ok(Some(0)) | Ok(None) => return Ok(()),
+                Ok(Some(_)) => attempt = 1,
+                Err(e) if attempt < self.retry.max_attempts && retry::is_transient(&e) => {
+                    let delay = self.retry.delay(attempt);
+                    warn!(
+                        "Reading source database failed with a transient error (attempt {}/{}), retrying in {:?}: {:#}",
+                        attempt, self.retry.max_attempts, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads one page after `cursor` and queues it as a batch, moving `cursor` past it once
+    /// handed over. The page is read in full before waiting on the throttle or the workers, so
+    /// the query never stays open meanwhile. Returns the number of keys read, or `None` once the
+    /// workers are gone or a stop is requested.
+    async fn queue_page(
+        &self,
+        cursor: &mut Cursor,
+        batches: &mpsc::Sender<Vec<String>>,
+    ) -> Result<Option<usize>> {
+        self.throttle.queries(1).await;
+        let keys = repo::find_raw_keys_after(
+            &self.pools.source,
+            self.strategy,
+            cursor,
+            &self.window,
+            &self.filter,
+            self.batch_size,
+        )
+        .await?;
+
+        let read = keys.len();
+        if read == 0 {
+            return Ok(Some(0));
+        }
+        let next = cursor
+            .clone()
+            .advance(self.strategy, keys.iter().map(Cursor::from));
+        let batch = keys.into_iter().map(|key| key.guid).collect::<Vec<_>>();
+
+        debug!("Queueing a batch of {} guids", batch.len());
+        self.throttle.rows(batch.len()).await;
+        if !self.send(batches, batch).await {
+            return Ok(None);
+        }
+        *cursor = next;
+
+        Ok(Some(read))
+    }
+
+    /// Queues `batch`, unless the workers are gone or a stop is requested first.
+    async fn send(&self, batches: &mpsc::Sender<Vec<String>>, batch: Vec<String>) -> bool {
+        tokio::select! {
+            sent = batches.send(batch) => sent.is_ok(),
+            _ = self.shutdown.requested() => false,
+        }
+    }
+}
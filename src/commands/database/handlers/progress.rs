@@ -0,0 +1,189 @@
+use std::{
+    io::{stdout, IsTerminal},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+
+/// Time between two progress lines when stdout is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+const OVERALL_TEMPLATE: &str =
+    "{elapsed_precise} [{bar:40}] {pos}/{len} notifications ({per_sec}, {msg}, ETA {eta})";
+const WORKER_TEMPLATE: &str = "  {prefix} {msg}";
+
+/// Bars on display, which log records are written around so they do not garble them.
+static DISPLAY: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Runs `write`, which prints to the terminal, with the progress bars hidden if any are shown.
+pub fn suspend<R>(write: impl FnOnce() -> R) -> R {
+    let display = DISPLAY.lock().expect("progress lock poisoned").clone();
+    match display {
+        Some(display) => display.suspend(write),
+        None => write(),
+    }
+}
+
+/// Progress of an import: an overall bar and one line per worker on a terminal, periodic log
+/// lines otherwise.
+#[derive(Debug)]
+pub struct Progress {
+    overall: ProgressBar,
+    workers: Vec<ProgressBar>,
+    terminal: bool,
+    started: Instant,
+    /// Rows written to target database, across every table
+    rows: AtomicU64,
+    logged: Mutex<Instant>,
+}
+
+impl Default for Progress {
+    /// Progress that is not displayed.
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            overall: ProgressBar::hidden(),
+            workers: vec![],
+            terminal: false,
+            started: now,
+            rows: AtomicU64::default(),
+            logged: Mutex::new(now),
+        }
+    }
+}
+
+impl Progress {
+    /// Progress of importing `total` notifications with `workers` workers, shown as bars when
+    /// stdout is a terminal.
+    pub fn new(total: u64, workers: usize) -> Self {
+        Self::with_terminal(total, workers, stdout().is_terminal())
+    }
+
+    /// Progress of importing `total` notifications with `workers` workers, shown as bars on
+    /// stdout when `terminal`, or as periodic log lines otherwise.
+    pub fn with_terminal(total: u64, workers: usize, terminal: bool) -> Self {
+        let mut progress = Self {
+            terminal,
+            ..Self::default()
+        };
+        if !terminal {
+            progress.overall.set_length(total);
+            return progress;
+        }
+
+        let display = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+        progress.overall = display.add(ProgressBar::new(total));
+        progress.overall.set_style(
+            ProgressStyle::with_template(OVERALL_TEMPLATE)
+                .expect("valid progress template")
+                .progress_chars("=> "),
+        );
+        progress.overall.set_message("0 rows/s");
+        progress.workers = (0..workers)
+            .map(|ix| {
+                let worker = display.add(ProgressBar::new_spinner());
+                worker.set_style(
+                    ProgressStyle::with_template(WORKER_TEMPLATE).expect("valid progress template"),
+                );
+                worker.set_prefix(format!("worker {}", ix));
+                worker.set_message("waiting");
+                worker
+            })
+            .collect();
+        progress
+            .overall
+            .enable_steady_tick(Duration::from_millis(200));
+        *DISPLAY.lock().expect("progress lock poisoned") = Some(display);
+
+        progress
+    }
+
+    /// Shows `guid` as the notification worker `ix` is working on.
+    pub fn current(&self, ix: usize, guid: &str) {
+        if let Some(worker) = self.workers.get(ix) {
+            worker.set_message(guid.to_owned());
+        }
+    }
+
+    /// Accounts for `count` more rows written to target database.
+    pub fn rows(&self, count: usize) {
+        let rows = self.rows.fetch_add(count as u64, Ordering::Relaxed) + count as u64;
+        if self.terminal {
+            self.overall
+                .set_message(format!("{:.1} rows/s", rate(rows, self.started.elapsed())));
+        }
+    }
+
+    /// Accounts for `count` more notifications done.
+    pub fn inc(&self, count: usize) {
+        self.overall.inc(count as u64);
+        if !self.terminal {
+            let mut logged = self.logged.lock().expect("progress lock poisoned");
+            if logged.elapsed() >= LOG_INTERVAL {
+                *logged = Instant::now();
+                self.log();
+            }
+        }
+    }
+
+    /// Removes the bars, or logs the last progress line.
+    pub fn finish(&self) {
+        if !self.terminal {
+            self.log();
+            return;
+        }
+        for worker in &self.workers {
+            worker.finish_and_clear();
+        }
+        self.overall.finish();
+        DISPLAY.lock().expect("progress lock poisoned").take();
+    }
+
+    fn log(&self) {
+        let elapsed = self.started.elapsed();
+        let done = self.overall.position();
+        let total = self.overall.length().unwrap_or_default();
+        let per_sec = rate(done, elapsed);
+        let eta = if per_sec > 0.0 && total > done {
+            HumanDuration(Duration::from_secs_f64((total - done) as f64 / per_sec)).to_string()
+        } else {
+            "-".to_owned()
+        };
+        info!(
+            "Imported {}/{} notifications ({:.1}/s, {:.1} rows/s, ETA {})",
+            done,
+            total,
+            per_sec,
+            rate(self.rows.load(Ordering::Relaxed), elapsed),
+            eta
+        );
+    }
+}
+
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    count as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_progress() {
+        let progress = Progress::with_terminal(10, 2, false);
+        assert!(progress.workers.is_empty());
+
+        progress.current(1, "guid");
+        progress.inc(4);
+        progress.inc(3);
+        progress.rows(12);
+        assert_eq!(progress.overall.position(), 7);
+        assert_eq!(progress.overall.length(), Some(10));
+        assert_eq!(progress.rows.load(Ordering::Relaxed), 12);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::commands::database::commands::DatabaseRepairArgs;
+use crate::commands::database::handlers::import::{
+    ConflictPolicy, Import, ImportSummary, RowCounts,
+};
+use crate::commands::database::handlers::shutdown::{Interrupted, Shutdown};
+use crate::commands::database::handlers::verify_handler::verify_range;
+use crate::commands::root::GlobalOpts;
+use crate::database::reconcile::{MismatchKind, VerifyReport};
+use crate::database::repo::Pools;
+use crate::database::retry::RetryPolicy;
+use crate::settings::{MergeSettings, Settings};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::fs::File;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// What a repair does with each mismatched notification.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RepairPlan {
+    /// Trees missing on target, or with missing child rows, written with `fill-missing`
+    fill: Vec<String>,
+    /// Differing trees replaced with a fresh copy of the source tree
+    recopy: Vec<String>,
+    /// Differing trees left untouched, as re-copying was not requested
+    differing: Vec<String>,
+    /// Notifications on target database only, never removed
+    extra: Vec<String>,
+}
+
+impl RepairPlan {
+    fn new(report: &VerifyReport, recopy: bool) -> Self {
+        let mut plan = Self::default();
+        for mismatch in &report.mismatches {
+            let guid = mismatch.guid.clone();
+            match mismatch.kind {
+                MismatchKind::Missing => plan.fill.push(guid),
+                MismatchKind::Extra => plan.extra.push(guid),
+                MismatchKind::Differs => {
+                    // A diff without a target value is a row missing on target, any other one
+                    // is a value or a row that only a fresh copy fixes.
+                    let changed = mismatch.diffs.iter().any(|diff| diff.target.is_some());
+                    let missing_rows = mismatch.diffs.iter().any(|diff| diff.target.is_none());
+                    if recopy && changed {
+                        plan.recopy.push(guid);
+                    } else if missing_rows {
+                        plan.fill.push(guid);
+                    } else {
+                        plan.differing.push(guid);
+                    }
+                }
+            }
+        }
+        plan
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fill.is_empty() && self.recopy.is_empty()
+    }
+
+    fn print(&self) {
+        println!(
+            "{:<30} {:>10}",
+            "Missing trees or rows to fill",
+            self.fill.len()
+        );
+        println!(
+            "{:<30} {:>10}",
+            "Differing trees to re-copy",
+            self.recopy.len()
+        );
+        println!(
+            "{:<30} {:>10}",
+            "Differing trees left as is",
+            self.differing.len()
+        );
+        println!("{:<30} {:>10}", "Extra trees left as is", self.extra.len());
+    }
+}
+
+pub async fn database_repair(
+    settings: &Settings,
+    globals: &GlobalOpts,
+    args: DatabaseRepairArgs,
+) -> Result<()> {
+    let args = args.merge(settings);
+    args.common_args.check_threads(args.threads)?;
+    let pools: Pools = Pools::try_from(&args).context("Error creating connection pools.")?;
+    let target_client_id = args
+        .target_client_id
+        .clone()
+        .context("Target client id is not defined.")?;
+
+    let report = match &args.report {
+        Some(path) => read_report(path)?,
+        None => {
+            verify_range(
+                &pools,
+                &args.window.window()?,
+                &args.filter.filter(),
+                args.batch_size,
+            )
+            .await?
+        }
+    };
+
+    let plan = RepairPlan::new(&report, args.recopy);
+    plan.print();
+    if plan.is_empty() {
+        info!("Nothing to repair");
+        return Ok(());
+    }
+
+    let retry = RetryPolicy::from(settings);
+    let import = |policy| {
+        Import::new(&pools, &target_client_id, args.threads)
+            .with_conflict_policy(policy)
+            .with_commit_every(args.write.commit_every())
+            .with_retry(retry)
+    };
+    let steps = [
+        (ConflictPolicy::FillMissing, &plan.fill),
+        (ConflictPolicy::Overwrite, &plan.recopy),
+    ];
+
+    if args.dry_run {
+        let mut to_write = RowCounts::default();
+        for (policy, guids) in steps {
+            let import = import(policy);
+            for batch in guids.chunks(args.batch_size as usize) {
+                to_write += import.dry_run(batch).await?.1;
+            }
+        }
+        print_dry_run(&to_write);
+        return Ok(());
+    }
+
+    if !args.yes && !confirm()? {
+        info!("Repair cancelled");
+        return Ok(());
+    }
+
+    let dead_letters = Arc::new(args.write.dead_letters()?);
+    let shutdown = Shutdown::install(args.write.shutdown_timeout());
+    for (policy, guids) in steps {
+        if guids.is_empty() {
+            continue;
+        }
+        let import = import(policy)
+            .with_dead_letters(&dead_letters)
+            .with_shutdown(&shutdown);
+        let mut summary = ImportSummary::new(policy);
+        for batch in guids.chunks(args.batch_size as usize) {
+            if shutdown.is_requested() {
+                warn!("Repair stopped before the end of the mismatches");
+                break;
+            }
+            match import.execute(batch).await {
+                Err(e) if e.downcast_ref::<Interrupted>().is_some() => break,
+                imported => summary.add(&imported?),
+            }
+        }
+        summary.print(globals.verbose > 0);
+    }
+
+    if shutdown.is_requested() {
+        return Err(Interrupted.into());
+    }
+    dead_letters.check()
+}
+
+fn read_report(path: &Path) -> Result<VerifyReport> {
+    let file = File::open(path)
+        .with_context(|| format!("Error while opening report file: {}", path.display()))?;
+    serde_json::from_reader(file)
+        .with_context(|| format!("Error while reading report file: {}", path.display()))
+}
+
+/// Asks on the terminal whether to go on with the repair.
+fn confirm() -> Result<bool> {
+    if !stdin().is_terminal() {
+        bail!("Repair needs a confirmation on a terminal, pass --yes to repair without it");
+    }
+    print!("Repair target database? [y/N] ");
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_dry_run(to_write: &RowCounts) {
+    println!("Dry run, nothing was written to target database.");
+    println!("{:<20} {:>10}", "Raw notifications", to_write.raws);
+    println!("{:<20} {:>10}", "Headers", to_write.headers);
+    println!("{:<20} {:>10}", "Items", to_write.items);
+    println!("{:<20} {:>10}", "Item data", to_write.data);
+    println!("{:<20} {:>10}", "Item operations", to_write.operations);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::reconcile::{FieldDiff, Mismatch};
+
+    fn differs(guid: &str, target: Option<&str>) -> Mismatch {
+        Mismatch {
+            diffs: vec![FieldDiff {
+                field: "raw.body".to_owned(),
+                source: Some("{}".to_owned()),
+                target: target.map(str::to_owned),
+            }],
+            ..Mismatch::new(guid, MismatchKind::Differs)
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let report = VerifyReport {
+            mismatches: vec![
+                Mismatch::new("missing", MismatchKind::Missing),
+                Mismatch::new("extra", MismatchKind::Extra),
+                differs("absent-row", None),
+                differs("changed", Some("NULL")),
+            ],
+            ..Default::default()
+        };
+        let guids = |guids: &[&str]| guids.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            RepairPlan::new(&report, false),
+            RepairPlan {
+                fill: guids(&["missing", "absent-row"]),
+                recopy: vec![],
+                differing: guids(&["changed"]),
+                extra: guids(&["extra"]),
+            }
+        );
+        assert_eq!(
+            RepairPlan::new(&report, true),
+            RepairPlan {
+                fill: guids(&["missing", "absent-row"]),
+                recopy: guids(&["changed"]),
+                differing: vec![],
+                extra: guids(&["extra"]),
+            }
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fmt::Display, future, io, process, time::Duration};
+
+use log::warn;
+use tokio::sync::watch;
+
+/// Graceful stop requested by SIGINT or SIGTERM.
+///
+/// Once requested, no new batch is fetched or started, and batches in flight get `grace` to
+/// commit before they are rolled back.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    grace: Duration,
+}
+
+impl Default for Shutdown {
+    /// A shutdown that is never requested.
+    fn default() -> Self {
+        let (_, requested) = watch::channel(false);
+        Self {
+            requested,
+            grace: Duration::ZERO,
+        }
+    }
+}
+
+impl Shutdown {
+    /// Listens for SIGINT and SIGTERM. The first signal requests a graceful stop, the second
+    /// exits right away.
+    pub fn install(grace: Duration) -> Self {
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            if signal().await.is_err() {
+                return;
+            }
+            warn!(
+                "Stopping: no new batches are started, batches in flight have {}s to commit. Press Ctrl-C again to force exit.",
+                grace.as_secs()
+            );
+            let _ = sender.send(true);
+
+            if signal().await.is_ok() {
+                warn!("Forced exit, uncommitted batches are rolled back by the server");
+                process::exit(Interrupted::EXIT_CODE);
+            }
+        });
+
+        Self { requested, grace }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once a stop is requested.
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        while !*requested.borrow_and_update() {
+            if requested.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Resolves once the grace period following a stop request is over.
+    pub async fn expired(&self) {
+        self.requested().await;
+        tokio::time::sleep(self.grace).await;
+    }
+}
+
+/// Returned by a run that a stop request cut short, whether the batches in flight were committed
+/// or rolled back once the grace period was over.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl Interrupted {
+    /// Process exit code of an interrupted run, also used when a second signal forces the exit.
+    pub const EXIT_CODE: i32 = 130;
+}
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Interrupted before the end of the run")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+#[cfg(unix)]
+async fn signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
@@ -1,15 +1,54 @@
-use crate::commands::database::commands::DatabaseStatusArgs;
+use crate::commands::database::commands::{DatabaseStatusArgs, OutputFormat};
+use crate::commands::database::handlers::checkpoint_handler::resume_point;
 use crate::commands::root::GlobalOpts;
+use crate::database::cursor::{Cursor, CursorStrategy, ItemFilter, TimeWindow};
+use crate::database::models::{PendingDay, PendingGroup};
 use crate::database::repo;
 use crate::database::repo::test_conn;
 use crate::database::repo::Pools;
 use crate::settings::MergeSettings;
 use crate::settings::Settings;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
 use sqlx::MySqlPool;
+use std::cmp::Reverse;
 use std::time::Duration;
 
+/// Where pending notifications are counted from.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CursorOrigin {
+    /// The checkpoint of the client id, or the last target notification when there is none
+    ResumePoint,
+    /// The last target notification, as no client id was given
+    TargetLast,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    cursor_origin: CursorOrigin,
+    cursor_created_date: NaiveDateTime,
+    cursor_uidpk: String,
+    pending: i64,
+    source_last_created_date: Option<NaiveDateTime>,
+    target_last_created_date: Option<NaiveDateTime>,
+    lag_minutes: Option<i64>,
+    by_day: Vec<Breakdown>,
+    /// Notifications with an item of each merchant account, so a notification with items of
+    /// several merchant accounts counts once under each
+    by_merchant_account: Vec<Breakdown>,
+    /// Notifications with an item of each event code, counted like `by_merchant_account`
+    by_event_code: Vec<Breakdown>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Breakdown {
+    key: String,
+    notifications: i64,
+}
+
 pub async fn database_status(
     settings: &Settings,
     _: &GlobalOpts,
@@ -18,47 +57,188 @@ pub async fn database_status(
     let args = args.merge(settings);
     let pools: Pools = Pools::try_from(&args)?;
 
-    test_connection(&pools.source, true).await?;
-    test_connection(&pools.target, true).await?;
+    let source = test_connection(&pools.source, "source").await;
+    let target = test_connection(&pools.target, "target").await;
+    match (source, target) {
+        (Ok(()), Ok(())) => {}
+        (Err(e), Ok(())) | (Ok(()), Err(e)) => return Err(e),
+        (Err(source), Err(target)) => bail!(
+            "Both connections failed. Source: {:#}. Target: {:#}",
+            source,
+            target
+        ),
+    }
+
+    let (cursor, cursor_origin) = match &args.target_client_id {
+        Some(client_id) => {
+            let key = args.common_args.checkpoint_key(client_id)?;
+            (
+                resume_point(&pools, &key, args.cursor).await?,
+                CursorOrigin::ResumePoint,
+            )
+        }
+        None => (
+            repo::get_last_raw_cursor(&pools.target, args.cursor)
+                .await?
+                .unwrap_or_default(),
+            CursorOrigin::TargetLast,
+        ),
+    };
+
+    let report = diff(
+        &pools,
+        args.cursor,
+        cursor,
+        cursor_origin,
+        &args.window.window()?,
+        &args.filter.filter(),
+    )
+    .await?;
 
-    diff(&pools.source, &pools.target).await?;
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => print_status(&report),
+    }
 
     Ok(())
 }
 
-async fn diff(source_conn: &MySqlPool, target_conn: &MySqlPool) -> Result<()> {
-    let spinner = ProgressBar::new_spinner();
-    spinner.enable_steady_tick(Duration::from_millis(100));
-    spinner.set_style(
-        ProgressStyle::with_template("{spinner:.blue} {msg}")
-            .unwrap()
-            .tick_strings(&[
-                "▹▹▹▹▹",
-                "▸▹▹▹▹",
-                "▹▸▹▹▹",
-                "▹▹▸▹▹",
-                "▹▹▹▸▹",
-                "▹▹▹▹▸",
-                "▪▪▪▪▪",
-            ]),
-    );
-
+async fn diff(
+    pools: &Pools,
+    strategy: CursorStrategy,
+    cursor: Cursor,
+    cursor_origin: CursorOrigin,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<StatusReport> {
+    let spinner = spinner();
     spinner.set_message(
         "Calculating the number of notifications are not sync with target database...",
     );
 
-    let last = repo::get_last_raw_created_date(target_conn).await?;
-    //let count = count_raw_notification_after(source_conn, &last).await?;
+    let pending = repo::count_raw_after(&pools.source, strategy, &cursor, window, filter).await?;
+    let days = repo::count_pending_by_day(&pools.source, strategy, &cursor, window, filter).await?;
+    let merchant_accounts =
+        repo::count_pending_by_merchant_account(&pools.source, strategy, &cursor, window, filter)
+            .await?;
+    let event_codes =
+        repo::count_pending_by_event_code(&pools.source, strategy, &cursor, window, filter).await?;
+    let source_last = repo::get_last_raw_created_date(&pools.source).await?;
+    let target_last = repo::get_last_raw_created_date(&pools.target).await?;
 
     spinner.finish_with_message(format!(
         "There are {} notifications not sync on target database.",
-        1
+        pending
     ));
 
-    Ok(())
+    let (by_day, by_merchant_account, by_event_code) =
+        breakdowns(&days, merchant_accounts, event_codes);
+    Ok(StatusReport {
+        cursor_origin,
+        cursor_created_date: cursor.created_date,
+        cursor_uidpk: cursor.uidpk.to_string(),
+        pending,
+        source_last_created_date: source_last,
+        target_last_created_date: target_last,
+        lag_minutes: source_last.map(|source| {
+            let target = target_last.unwrap_or_default();
+            source.signed_duration_since(target).num_minutes().max(0)
+        }),
+        by_day,
+        by_merchant_account,
+        by_event_code,
+    })
 }
 
-async fn test_connection(pool: &MySqlPool, source: bool) -> Result<()> {
+/// Lists `days` in date order, and the notifications of each merchant account and event code
+/// largest first.
+fn breakdowns(
+    days: &[PendingDay],
+    merchant_accounts: Vec<PendingGroup>,
+    event_codes: Vec<PendingGroup>,
+) -> (Vec<Breakdown>, Vec<Breakdown>, Vec<Breakdown>) {
+    let by_day = days
+        .iter()
+        .map(|day| Breakdown {
+            key: day.day.to_string(),
+            notifications: day.notifications,
+        })
+        .collect();
+    (
+        by_day,
+        largest_first(merchant_accounts),
+        largest_first(event_codes),
+    )
+}
+
+fn largest_first(groups: Vec<PendingGroup>) -> Vec<Breakdown> {
+    let mut breakdown = groups
+        .into_iter()
+        .map(|group| Breakdown {
+            key: group.key,
+            notifications: group.notifications,
+        })
+        .collect::<Vec<_>>();
+    breakdown.sort_by(|a, b| {
+        (Reverse(a.notifications), &a.key).cmp(&(Reverse(b.notifications), &b.key))
+    });
+    breakdown
+}
+
+fn print_status(report: &StatusReport) {
+    println!(
+        "Cursor: created date {} and uidpk {} ({})",
+        report.cursor_created_date,
+        report.cursor_uidpk,
+        match report.cursor_origin {
+            CursorOrigin::ResumePoint => "sync resume point",
+            CursorOrigin::TargetLast => "last target notification",
+        }
+    );
+    println!("{:<30} {:>10}", "Pending notifications", report.pending);
+    println!(
+        "{:<30} {:>10}",
+        "Source last created date",
+        display_date(report.source_last_created_date)
+    );
+    println!(
+        "{:<30} {:>10}",
+        "Target last created date",
+        display_date(report.target_last_created_date)
+    );
+    println!(
+        "{:<30} {:>10}",
+        "Lag in minutes",
+        report
+            .lag_minutes
+            .map(|lag| lag.to_string())
+            .unwrap_or_else(|| "-".to_owned())
+    );
+
+    for (title, breakdown) in [
+        ("By day:", &report.by_day),
+        (
+            "With items of merchant account:",
+            &report.by_merchant_account,
+        ),
+        ("With items of event code:", &report.by_event_code),
+    ] {
+        if breakdown.is_empty() {
+            continue;
+        }
+        println!("{}", title);
+        for row in breakdown {
+            println!("  {:<28} {:>10}", row.key, row.notifications);
+        }
+    }
+}
+
+fn display_date(date: Option<NaiveDateTime>) -> String {
+    date.map(|date| date.to_string())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+fn spinner() -> ProgressBar {
     let spinner = ProgressBar::new_spinner();
     spinner.enable_steady_tick(Duration::from_millis(100));
     spinner.set_style(
@@ -76,17 +256,72 @@ async fn test_connection(pool: &MySqlPool, source: bool) -> Result<()> {
                 "▪▪▪▪▪",
             ]),
     );
-    let conn_type = if source { "source" } else { "target" };
-    spinner.set_message(format!("Verifyng and test {} connection...", conn_type));
+    spinner
+}
+
+async fn test_connection(pool: &MySqlPool, label: &str) -> Result<()> {
+    let spinner = spinner();
+    spinner.set_message(format!("Verifyng and test {} connection...", label));
 
     match test_conn(pool).await {
         Ok(_) => {
-            spinner.finish_with_message(format!("{} connection verified successfully", conn_type));
+            spinner.finish_with_message(format!("{} connection verified successfully", label));
             Ok(())
         }
         Err(e) => {
-            spinner.finish_with_message(format!("{} connection verification failed", conn_type));
-            Err(e)
+            spinner.finish_with_message(format!("{} connection verification failed", label));
+            Err(e.context(format!("Error while verifying {} connection", label)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn day(day: u32, notifications: i64) -> PendingDay {
+        PendingDay {
+            day: NaiveDate::from_ymd_opt(2023, 3, day).unwrap(),
+            notifications,
         }
     }
+
+    fn group(key: &str, notifications: i64) -> PendingGroup {
+        PendingGroup {
+            key: key.to_owned(),
+            notifications,
+        }
+    }
+
+    fn breakdown(key: &str, notifications: i64) -> Breakdown {
+        Breakdown {
+            key: key.to_owned(),
+            notifications,
+        }
+    }
+
+    #[test]
+    fn test_breakdowns() {
+        // A notification with items of both event codes is counted once under each, and once
+        // under its merchant account.
+        let (by_day, by_merchant_account, by_event_code) = breakdowns(
+            &[day(1, 1), day(2, 7)],
+            vec![group("shop", 3), group("market", 5)],
+            vec![group("REFUND", 1), group("AUTHORISATION", 7)],
+        );
+
+        assert_eq!(
+            by_day,
+            vec![breakdown("2023-03-01", 1), breakdown("2023-03-02", 7)]
+        );
+        assert_eq!(
+            by_merchant_account,
+            vec![breakdown("market", 5), breakdown("shop", 3)]
+        );
+        assert_eq!(
+            by_event_code,
+            vec![breakdown("AUTHORISATION", 7), breakdown("REFUND", 1)]
+        );
+    }
 }
@@ -1,69 +1,478 @@
-use crate::commands::database::commands::DatabaseSyncArgs;
-use crate::commands::database::handlers::import::Import;
+use crate::commands::database::commands::{DatabaseSyncArgs, OutputFormat};
+use crate::commands::database::handlers::checkpoint_handler::resume_point;
+use crate::commands::database::handlers::import::{
+    Import, ImportOutcome, ImportSummary, RowCounts,
+};
+use crate::commands::database::handlers::metrics::Metrics;
+use crate::commands::database::handlers::producer::Producer;
+use crate::commands::database::handlers::progress::Progress;
+use crate::commands::database::handlers::shutdown::{Interrupted, Shutdown};
 use crate::commands::root::GlobalOpts;
+use crate::database::bulk::MAX_IN_VALUES;
+use crate::database::cursor::{Cursor, ItemFilter, TimeWindow};
 use crate::database::repo::Pools;
 use crate::database::repo::{self};
+use crate::database::retry::RetryPolicy;
 use crate::settings::{MergeSettings, Settings};
 use anyhow::{Context, Result};
 use chrono::{NaiveDateTime, Utc};
+use indicatif::HumanDuration;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{stdin, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 
 pub async fn databse_sync(
     settings: &Settings,
-    _: &GlobalOpts,
+    globals: &GlobalOpts,
     args: DatabaseSyncArgs,
 ) -> Result<()> {
     let start = Utc::now();
 
     let args = args.merge(settings);
+    args.args.check_threads(args.threads)?;
     let pools: Pools = Pools::try_from(&args).context("Error creating connection pools.")?;
     let target_client_id = args
         .target_client_id
+        .clone()
         .context("Target client id is not defined.")?;
 
-    let mut last_created_date = repo::get_last_raw_created_date(&pools.target)
-        .await
-        .context("Error while fetching target mas raw uidpk")?
-        .unwrap_or(NaiveDateTime::from_timestamp_millis(0).context("invalid date")?);
+    if let Some(path) = &args.guids_file {
+        let guids = read_guids(path)?;
+        return sync_guids(settings, &pools, &args, &target_client_id, &guids).await;
+    }
 
-    println!("Starting to sync target database.....");
-    println!(
-        "The last raw notification created date on target database is: {}",
-        &last_created_date
-    );
+    let checkpoint = args.args.checkpoint_key(&target_client_id)?;
+    let window = args.window.window()?;
+    let filter = args.filter.filter();
+    // A bounded or filtered run copies its selection from the start and leaves the persistent
+    // cursor alone, so a later full sync still sees what it did not select.
+    let partial = window.is_bounded() || !filter.is_empty();
 
-    let total = repo::count_raw_created_date(&pools.source, &last_created_date).await?;
-    println!(
-        "There are {} notifications to be imported from source database.",
+    let cursor = if partial {
+        Cursor::default()
+    } else {
+        resume_point(&pools, &checkpoint, args.cursor).await?
+    };
+
+    if args.dry_run {
+        return dry_run(&pools, &args, &target_client_id, &window, &filter, cursor).await;
+    }
+
+    info!("Starting to sync target database");
+    if partial {
+        info!(
+            "Syncing notifications created from {} to {}, the checkpoint is left untouched",
+            display_bound(window.from),
+            display_bound(window.to)
+        );
+        if !filter.is_empty() {
+            info!("Item filter: {:?}", filter);
+        }
+    } else {
+        repo::create_checkpoint_table(&pools.target).await?;
+        info!(
+            "Resuming from created date {} and uidpk {}",
+            &cursor.created_date, &cursor.uidpk
+        );
+    }
+
+    let shutdown = Shutdown::install(args.write.shutdown_timeout());
+    let retry = RetryPolicy::from(settings);
+    let throttle = Arc::new(args.throttle.throttle());
+    throttle.queries(1).await;
+    let total = retry
+        .retry("Counting notifications", || {
+            repo::count_raw_after(&pools.source, args.cursor, &cursor, &window, &filter)
+        })
+        .await?;
+    info!(
+        "There are {} notifications to be imported from source database",
         total
     );
 
-    println!("Let's go!");
+    let dead_letters = Arc::new(args.write.dead_letters()?);
+    let metrics = Arc::new(Metrics::default());
+    let progress = Arc::new(Progress::new(total as u64, args.threads as usize));
+    let mut summary = ImportSummary::new(args.on_conflict);
+
+    // The producer keeps up to one batch per worker queued ahead of the workers.
+    let (batches, receiver) = mpsc::channel(args.threads.max(1) as usize);
+    let producer = Producer::new(&pools, args.cursor, args.batch_size)
+        .with_window(window)
+        .with_filter(filter)
+        .with_retry(retry)
+        .with_throttle(&throttle)
+        .with_shutdown(&shutdown);
+    let producer = tokio::spawn(producer.run(cursor.clone(), batches));
+
+    let mut import = Import::new(&pools, &target_client_id, args.threads)
+        .with_conflict_policy(args.on_conflict)
+        .with_commit_every(args.write.commit_every())
+        .with_dead_letters(&dead_letters)
+        .with_retry(retry)
+        .with_throttle(&throttle)
+        .with_shutdown(&shutdown)
+        .with_metrics(&metrics)
+        .with_progress(&progress);
+    if !partial {
+        import = import.with_checkpoint(&checkpoint, args.cursor);
+    }
+    let mut committed = cursor;
+    let imported = import
+        .run(receiver, |report| {
+            progress.inc(report.outcomes.len());
+            summary.add(&report);
+            committed = committed
+                .clone()
+                .advance(args.cursor, report.raws.iter().map(Cursor::from));
+        })
+        .await;
+    progress.finish();
+    // Batches rolled back once the grace period is over are left out of `committed`, so an
+    // interrupted run is reported like a stopped one.
+    if let Err(e) = imported {
+        if e.downcast_ref::<Interrupted>().is_none() {
+            producer.abort();
+            push_metrics(&args, &pools, &target_client_id, &metrics, Some(&committed)).await;
+            return Err(e);
+        }
+    }
+    if shutdown.is_requested() {
+        producer.abort();
+    } else {
+        producer.await??;
+    }
+
+    summary.print(globals.verbose > 0);
+    if shutdown.is_requested() {
+        warn!(
+            "Sync stopped, notifications are committed up to created date {} and uidpk {}{}",
+            committed.created_date,
+            committed.uidpk,
+            if partial {
+                ""
+            } else {
+                ", the checkpoint was saved there"
+            }
+        );
+    }
+
+    info!(
+        "Sync finished in {}",
+        HumanDuration(
+            Utc::now()
+                .signed_duration_since(start)
+                .to_std()
+                .unwrap_or_default()
+        )
+    );
+
+    push_metrics(&args, &pools, &target_client_id, &metrics, Some(&committed)).await;
+    if shutdown.is_requested() {
+        return Err(Interrupted.into());
+    }
+    dead_letters.check()
+}
+
+/// Pushes the metrics of the run when a push gateway is configured, with the lag left behind
+/// source database when `committed` is known. A failed push does not fail the run.
+async fn push_metrics(
+    args: &DatabaseSyncArgs,
+    pools: &Pools,
+    target_client_id: &str,
+    metrics: &Metrics,
+    committed: Option<&Cursor>,
+) {
+    let Some(gateway) = &args.push_gateway else {
+        return;
+    };
+    if let Some(committed) = committed {
+        match repo::get_last_raw_created_date(&pools.source).await {
+            Ok(last) => metrics.set_lag(last.map(|last| last - committed.created_date)),
+            Err(e) => warn!("Could not compute the lag behind source database: {:#}", e),
+        }
+    }
+    if let Err(e) = metrics
+        .push(gateway, "adyen_sync", target_client_id, pools)
+        .await
+    {
+        warn!("Pushing metrics to {} failed: {:#}", gateway, e);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DryRunReport {
+    batches: usize,
+    to_insert: RowCounts,
+    conflicts: Vec<DryRunConflict>,
+    not_found: Vec<String>,
+    cursor_created_date: NaiveDateTime,
+    cursor_uidpk: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DryRunConflict {
+    guid: String,
+    outcome: &'static str,
+}
+
+/// Walks the same cursor and batches as a real sync, without writing to target database.
+async fn dry_run(
+    pools: &Pools,
+    args: &DatabaseSyncArgs,
+    target_client_id: &str,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+    mut cursor: Cursor,
+) -> Result<()> {
+    let import =
+        Import::new(pools, target_client_id, args.threads).with_conflict_policy(args.on_conflict);
+    let throttle = Arc::new(args.throttle.throttle());
+    let import = import.with_throttle(&throttle);
+    let mut batches = 0;
+    let mut to_insert = RowCounts::default();
+    let mut conflicts = vec![];
+    let mut not_found = vec![];
 
     loop {
-        let guids_to_import = repo::find_raw_guid_after_created_date(
+        throttle.queries(1).await;
+        let keys = repo::find_raw_keys_after(
             &pools.source,
-            &last_created_date,
-            args.batch_size as u64,
+            args.cursor,
+            &cursor,
+            window,
+            filter,
+            args.batch_size,
         )
         .await?;
 
-        if guids_to_import.is_empty() {
+        if keys.is_empty() {
             break;
         }
 
-        println!("Iniciando a importacao {}", guids_to_import.len());
-        let result = Import::new(&pools, &target_client_id, args.threads)
-            .execute(&guids_to_import)
-            .await?;
+        cursor = cursor.advance(args.cursor, keys.iter().map(Cursor::from));
+        let guids = keys.into_iter().map(|key| key.guid).collect::<Vec<_>>();
+        batches += 1;
 
-        last_created_date = result
-            .iter()
-            .map(|r| r.created_date)
-            .max()
-            .unwrap_or(last_created_date);
+        for unit in guids.chunks(MAX_IN_VALUES) {
+            let (report, counts) = import.dry_run(unit).await?;
+            to_insert += counts;
+            for (guid, outcome) in report.outcomes {
+                match outcome {
+                    ImportOutcome::Inserted => {}
+                    ImportOutcome::NotFound => not_found.push(guid),
+                    _ => conflicts.push(DryRunConflict {
+                        guid,
+                        outcome: outcome.label(),
+                    }),
+                }
+            }
+        }
     }
 
-    print!("finish: {}", start.signed_duration_since(Utc::now()));
+    let report = DryRunReport {
+        batches,
+        to_insert,
+        conflicts,
+        not_found,
+        cursor_created_date: cursor.created_date,
+        cursor_uidpk: cursor.uidpk.to_string(),
+    };
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => print_dry_run(&report, args),
+    }
 
     Ok(())
 }
+
+fn print_dry_run(report: &DryRunReport, args: &DatabaseSyncArgs) {
+    println!("Dry run, nothing was written to target database.");
+    println!("Conflict policy: {}", args.on_conflict);
+    println!("{:<20} {:>10}", "Batches", report.batches);
+    println!("{:<20} {:>10}", "Raw notifications", report.to_insert.raws);
+    println!("{:<20} {:>10}", "Headers", report.to_insert.headers);
+    println!("{:<20} {:>10}", "Items", report.to_insert.items);
+    println!("{:<20} {:>10}", "Item data", report.to_insert.data);
+    println!(
+        "{:<20} {:>10}",
+        "Item operations", report.to_insert.operations
+    );
+    println!(
+        "Cursor would end at created date {} and uidpk {}",
+        report.cursor_created_date, report.cursor_uidpk
+    );
+
+    if !report.conflicts.is_empty() {
+        println!("Conflicts:");
+        for conflict in &report.conflicts {
+            println!("  {} -> {}", conflict.guid, conflict.outcome);
+        }
+    }
+
+    if !report.not_found.is_empty() {
+        println!("Not found on source database:");
+        for guid in &report.not_found {
+            println!("  {}", guid);
+        }
+    }
+}
+
+fn display_bound(bound: Option<NaiveDateTime>) -> String {
+    bound
+        .map(|date| date.to_string())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+/// Reads one guid per line from `path`, or from stdin when it is `-`. Blank lines and lines
+/// starting with `#` are ignored, and repeated guids are kept once.
+pub fn read_guids(path: &Path) -> Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(BufReader::new(stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path).with_context(|| {
+            format!("Error while opening guids file: {}", path.display())
+        })?))
+    };
+
+    let mut seen = HashSet::new();
+    let mut guids = vec![];
+    for line in reader.lines() {
+        let line = line.context("Error while reading guids")?;
+        let guid = line.trim();
+        if guid.is_empty() || guid.starts_with('#') {
+            continue;
+        }
+        if seen.insert(guid.to_owned()) {
+            guids.push(guid.to_owned());
+        }
+    }
+    Ok(guids)
+}
+
+#[derive(Debug, Default, Serialize)]
+struct GuidsReport {
+    inserted: Vec<String>,
+    already_present: Vec<GuidOutcome>,
+    missing: Vec<String>,
+    dead_lettered: Vec<GuidError>,
+}
+
+#[derive(Debug, Serialize)]
+struct GuidError {
+    guid: String,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GuidOutcome {
+    guid: String,
+    outcome: &'static str,
+}
+
+/// Imports an explicit list of guids, bypassing the cursor and the checkpoint.
+async fn sync_guids(
+    settings: &Settings,
+    pools: &Pools,
+    args: &DatabaseSyncArgs,
+    target_client_id: &str,
+    guids: &[String],
+) -> Result<()> {
+    info!(
+        "Syncing {} guids from the list{}",
+        guids.len(),
+        if args.dry_run { " (dry run)" } else { "" }
+    );
+
+    let metrics = Arc::new(Metrics::default());
+    let progress = Arc::new(Progress::new(guids.len() as u64, args.threads as usize));
+    let mut import = Import::new(pools, target_client_id, args.threads)
+        .with_conflict_policy(args.on_conflict)
+        .with_commit_every(args.write.commit_every())
+        .with_retry(RetryPolicy::from(settings))
+        .with_throttle(&Arc::new(args.throttle.throttle()))
+        .with_metrics(&metrics)
+        .with_progress(&progress);
+    let mut dead_letters = None;
+    if !args.dry_run {
+        let opened = Arc::new(args.write.dead_letters()?);
+        import = import.with_dead_letters(&opened);
+        dead_letters = Some(opened);
+    }
+    let mut report = GuidsReport::default();
+
+    let shutdown = Shutdown::install(args.write.shutdown_timeout());
+    import = import.with_shutdown(&shutdown);
+    for batch in guids.chunks(args.batch_size as usize) {
+        if shutdown.is_requested() {
+            warn!("Stopped before the end of the list");
+            break;
+        }
+        let outcomes = if args.dry_run {
+            import.dry_run(batch).await?.0.outcomes
+        } else {
+            match import.execute(batch).await {
+                Err(e) if e.downcast_ref::<Interrupted>().is_some() => break,
+                imported => imported?.outcomes,
+            }
+        };
+        progress.inc(outcomes.len());
+
+        for (guid, outcome) in outcomes {
+            match outcome {
+                ImportOutcome::Inserted => report.inserted.push(guid),
+                ImportOutcome::NotFound => report.missing.push(guid),
+                ImportOutcome::DeadLettered(error) => {
+                    report.dead_lettered.push(GuidError { guid, error })
+                }
+                _ => report.already_present.push(GuidOutcome {
+                    guid,
+                    outcome: outcome.label(),
+                }),
+            }
+        }
+    }
+
+    progress.finish();
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => {
+            println!("Conflict policy: {}", args.on_conflict);
+            println!(
+                "Found: {}",
+                report.inserted.len() + report.already_present.len() + report.dead_lettered.len()
+            );
+            println!("Inserted: {}", report.inserted.len());
+            println!("Already present: {}", report.already_present.len());
+            for present in &report.already_present {
+                println!("  {} -> {}", present.guid, present.outcome);
+            }
+            println!("Missing on source database: {}", report.missing.len());
+            for guid in &report.missing {
+                println!("  {}", guid);
+            }
+            println!("Dead-lettered: {}", report.dead_lettered.len());
+            for failed in &report.dead_lettered {
+                println!("  {} -> {}", failed.guid, failed.error);
+            }
+        }
+    }
+
+    if !args.dry_run {
+        push_metrics(args, pools, target_client_id, &metrics, None).await;
+    }
+    if shutdown.is_requested() {
+        return Err(Interrupted.into());
+    }
+    match dead_letters {
+        Some(dead_letters) => dead_letters.check(),
+        None => Ok(()),
+    }
+}
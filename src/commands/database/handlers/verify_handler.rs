@@ -0,0 +1,276 @@
+use crate::commands::database::commands::{DatabaseVerifyArgs, OutputFormat};
+use crate::commands::database::handlers::sync_handler::read_guids;
+use crate::commands::root::GlobalOpts;
+use crate::database::cursor::{Cursor, CursorStrategy, ItemFilter, TimeWindow};
+use crate::database::models::NotificationTree;
+use crate::database::reconcile::{
+    differing_buckets, BucketSize, ChecksumDiff, Mismatch, MismatchKind, VerifyReport,
+};
+use crate::database::repo::{self, Pools};
+use crate::settings::{MergeSettings, Settings};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use sqlx::MySqlPool;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Display;
+use std::fs::File;
+
+pub async fn database_verify(
+    settings: &Settings,
+    _: &GlobalOpts,
+    args: DatabaseVerifyArgs,
+) -> Result<()> {
+    let args = args.merge(settings);
+    let pools: Pools = Pools::try_from(&args).context("Error creating connection pools.")?;
+
+    let report = match &args.guids_file {
+        Some(path) => verify_guids(&pools, &read_guids(path)?, args.batch_size).await?,
+        None if args.fast => {
+            verify_fast(
+                &pools,
+                &args.window.window()?,
+                &args.filter.filter(),
+                args.bucket,
+                args.batch_size,
+            )
+            .await?
+        }
+        None => {
+            verify_range(
+                &pools,
+                &args.window.window()?,
+                &args.filter.filter(),
+                args.batch_size,
+            )
+            .await?
+        }
+    };
+
+    if let Some(path) = &args.report_file {
+        let file = File::create(path)
+            .with_context(|| format!("Error while creating report file: {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &report)
+            .with_context(|| format!("Error while writing report file: {}", path.display()))?;
+    }
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => print_report(&report),
+    }
+
+    match report.mismatches.len() {
+        0 => Ok(()),
+        count => Err(Mismatched { count }.into()),
+    }
+}
+
+async fn verify_guids(pools: &Pools, guids: &[String], batch_size: u64) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    for chunk in guids.chunks(batch_size as usize) {
+        let (source, target) = fetch_trees(pools, chunk).await?;
+        report.add(chunk, source, target);
+    }
+    info!("Verified {} listed guids", guids.len());
+    Ok(report)
+}
+
+/// Compares every source notification of the range with its target copy, then looks for
+/// target notifications of the range that are not on source database.
+pub async fn verify_range(
+    pools: &Pools,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+    batch_size: u64,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    let mut cursor = Cursor::default();
+    loop {
+        let guids = next_guids(&pools.source, &mut cursor, window, filter, batch_size).await?;
+        if guids.is_empty() {
+            break;
+        }
+        let (source, target) = fetch_trees(pools, &guids).await?;
+        report.add(&guids, source, target);
+        info!("Verified {} source notifications", report.checked);
+    }
+
+    let mut cursor = Cursor::default();
+    let mut source = pools.source.acquire().await?;
+    loop {
+        let guids = next_guids(&pools.target, &mut cursor, window, filter, batch_size).await?;
+        if guids.is_empty() {
+            break;
+        }
+        let existing = repo::find_existing_raw_guids(&mut source, &guids)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        report.extra(guids.into_iter().filter(|guid| !existing.contains(guid)));
+    }
+
+    Ok(report)
+}
+
+/// Compares checksums per bucket of created dates, splitting the buckets that differ until the
+/// differing notifications are found, which are then compared row by row.
+async fn verify_fast(
+    pools: &Pools,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+    bucket: BucketSize,
+    batch_size: u64,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut notifications = None;
+    let mut buckets = VecDeque::from([(*window, bucket)]);
+    let mut differs = vec![];
+
+    while let Some((window, size)) = buckets.pop_front() {
+        let source =
+            repo::find_checksum_buckets(&pools.source, size.format(), &window, filter).await?;
+        let target =
+            repo::find_checksum_buckets(&pools.target, size.format(), &window, filter).await?;
+        notifications.get_or_insert_with(|| source.iter().map(|b| b.notifications).sum::<i64>());
+        let differing = differing_buckets(&source, &target);
+        debug!(
+            "{} of {} buckets from {:?} to {:?} differ",
+            differing.len(),
+            source.len().max(target.len()),
+            window.from,
+            window.to
+        );
+
+        for bucket in differing {
+            let window = size.window(&bucket, &window)?;
+            if let Some(finer) = size.finer() {
+                buckets.push_back((window, finer));
+                continue;
+            }
+
+            let source = repo::find_notification_checksums(&pools.source, &window, filter).await?;
+            let target = repo::find_notification_checksums(&pools.target, &window, filter).await?;
+            let diff = ChecksumDiff::new(&source, &target);
+            for guid in diff.missing {
+                report
+                    .mismatches
+                    .push(Mismatch::new(&guid, MismatchKind::Missing));
+            }
+            report.extra(diff.extra);
+            differs.extend(diff.differs);
+        }
+    }
+
+    for chunk in differs.chunks(batch_size as usize) {
+        let (source, target) = fetch_trees(pools, chunk).await?;
+        report.add(chunk, source, target);
+    }
+
+    // Notifications in matching buckets were not read but match all the same.
+    let notifications = notifications.unwrap_or_default() as usize;
+    let extra = report
+        .mismatches
+        .iter()
+        .filter(|mismatch| mismatch.kind == MismatchKind::Extra)
+        .count();
+    report.checked = notifications + extra;
+    report.matched = notifications.saturating_sub(report.mismatches.len() - extra);
+    info!(
+        "Verified {} notifications, {} differ",
+        report.checked,
+        report.mismatches.len()
+    );
+    Ok(report)
+}
+
+/// Pages the guids of `pool` inside the range, advancing `cursor` past them.
+async fn next_guids(
+    pool: &MySqlPool,
+    cursor: &mut Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+    batch_size: u64,
+) -> Result<Vec<String>> {
+    let strategy = CursorStrategy::Composite;
+    let keys =
+        repo::find_raw_keys_after(pool, strategy, cursor, window, filter, batch_size).await?;
+    *cursor = std::mem::take(cursor).advance(strategy, keys.iter().map(Cursor::from));
+    Ok(keys.into_iter().map(|key| key.guid).collect())
+}
+
+/// Fetches the trees of `guids` from source and target databases.
+async fn fetch_trees(
+    pools: &Pools,
+    guids: &[String],
+) -> Result<(Vec<NotificationTree>, Vec<NotificationTree>)> {
+    let mut source = pools.source.acquire().await?;
+    let mut target = pools.target.acquire().await?;
+    let source = repo::find_trees_by_guids(&mut source, guids)
+        .await
+        .context("Error while reading source notifications")?;
+    let target = repo::find_trees_by_guids(&mut target, guids)
+        .await
+        .context("Error while reading target notifications")?;
+    Ok((source, target))
+}
+
+fn print_report(report: &VerifyReport) {
+    println!("{:<30} {:>10}", "Checked notifications", report.checked);
+    println!("{:<30} {:>10}", "Matched", report.matched);
+    for (title, kind) in [
+        ("Missing on target", MismatchKind::Missing),
+        ("Extra on target", MismatchKind::Extra),
+        ("Different", MismatchKind::Differs),
+    ] {
+        let count = report
+            .mismatches
+            .iter()
+            .filter(|mismatch| mismatch.kind == kind)
+            .count();
+        println!("{:<30} {:>10}", title, count);
+    }
+    if !report.not_found.is_empty() {
+        println!("{:<30} {:>10}", "Not found", report.not_found.len());
+    }
+
+    for mismatch in &report.mismatches {
+        match mismatch.kind {
+            MismatchKind::Missing => println!("{} missing on target", mismatch.guid),
+            MismatchKind::Extra => println!("{} extra on target", mismatch.guid),
+            MismatchKind::Differs => {
+                println!("{} differs", mismatch.guid);
+                for diff in &mismatch.diffs {
+                    println!(
+                        "  {}: {} -> {}",
+                        diff.field,
+                        diff.source.as_deref().unwrap_or("<absent>"),
+                        diff.target.as_deref().unwrap_or("<absent>")
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returned by a verify run that found mismatched notifications.
+#[derive(Debug)]
+pub struct Mismatched {
+    pub count: usize,
+}
+
+impl Mismatched {
+    /// Process exit code of a verify run that found mismatches.
+    pub const EXIT_CODE: i32 = 4;
+}
+
+impl Display for Mismatched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} notifications differ between source and target databases",
+            self.count
+        )
+    }
+}
+
+impl std::error::Error for Mismatched {}
@@ -1,51 +1,258 @@
-use std::{
-    io::{stdout, Write},
-    thread,
-    time::Duration,
-};
+use std::{future::Future, pin::pin, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use chrono::{Duration as ChronoDuration, Utc};
+use log::{error, info, warn};
 
 use crate::{
     commands::{
         database::{
             commands::DatabaseWatchArgs,
-            handlers::import::{self, Import},
+            handlers::{
+                checkpoint_handler::resume_point,
+                dead_letter::DeadLetters,
+                health::{Health, HealthServer},
+                import::Import,
+                metrics::Metrics,
+                shutdown::{Interrupted, Shutdown},
+            },
         },
         root::GlobalOpts,
     },
-    database::repo::{self, Pools},
+    database::{
+        cursor::{Cursor, ItemFilter, TimeWindow},
+        models::CheckpointKey,
+        repo::{self, Pools},
+        retry::RetryPolicy,
+        throttle::Throttle,
+    },
     settings::{MergeSettings, Settings},
 };
 
+/// Interval between heartbeats while a poll waits on a pause window or imports a long batch.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
 pub async fn database_watch(
     settings: &Settings,
     _: &GlobalOpts,
     args: DatabaseWatchArgs,
 ) -> Result<()> {
-    println!("start watching");
+    info!("Start watching");
     let args = args.merge(settings);
     let pools = Pools::try_from(&args).context("Error creating connection pools.")?;
-    let mut ref_time = Utc::now().naive_utc() - ChronoDuration::days(100);
-    loop {
-        let raws = repo::find_raw_guid_after_created_date(&pools.source, &ref_time, 200).await?;
-
-        let imported = Import::new(
-            &pools,
-            args.target_client_id
-                .as_ref()
-                .context("deveria ter passado")?,
-            1,
-        )
-        .execute(&raws)
-        .await;
-
-        //let max_impoted_created_date = imported.iter().map(|r| r.created_date).max();
-        // if let Some(date) = max_impoted_created_date {
-        //     ref_time = date;
-        // }
-
-        thread::sleep(Duration::from_secs(args.delay as u64));
+    let target_client_id = args
+        .target_client_id
+        .as_ref()
+        .context("Target client id is not defined.")?;
+    let checkpoint = args.common_args.checkpoint_key(target_client_id)?;
+
+    repo::create_checkpoint_table(&pools.target).await?;
+    let mut cursor = resume_point(&pools, &checkpoint, args.cursor).await?;
+    info!(
+        "Following from created date {} and uidpk {}",
+        cursor.created_date, cursor.uidpk
+    );
+
+    let delay = Duration::from_secs(args.delay as u64);
+    let max_delay = Duration::from_secs(args.max_delay as u64).max(delay);
+
+    let metrics = Arc::new(Metrics::default());
+
+    // Polls send heartbeats while they run, so only the sleep of up to `max_delay` between two
+    // polls goes without one.
+    let health = args.http_listen.map(|address| {
+        let health = Arc::new(Health::new(max_delay * 2 + Duration::from_secs(60)));
+        let server = HealthServer::new(&health, &pools).with_metrics(&metrics);
+        tokio::spawn(async move {
+            if let Err(e) = server.bind(address).await {
+                error!("Health endpoints stopped: {:#}", e);
+            }
+        });
+        health
+    });
+
+    let follower = Follower {
+        pools: &pools,
+        args: &args,
+        client_id: target_client_id,
+        checkpoint: &checkpoint,
+        dead_letters: Arc::new(args.write.dead_letters()?),
+        retry: RetryPolicy::from(settings),
+        throttle: Arc::new(args.throttle.throttle()),
+        shutdown: Shutdown::install(args.write.shutdown_timeout()),
+        health,
+        metrics,
+    };
+
+    let mut idle_delay = delay;
+    let mut interrupted = false;
+
+    while !follower.shutdown.is_requested() {
+        let polled = follower.beating(follower.poll(&mut cursor)).await;
+        follower.report(&cursor, &polled).await;
+        let wait = match polled {
+            // A full batch means source database is ahead, so poll again right away.
+            Ok(read) if read as u64 >= args.batch_size => {
+                idle_delay = delay;
+                continue;
+            }
+            Ok(0) => {
+                let wait = idle_delay;
+                idle_delay = (idle_delay * 2).min(max_delay);
+                wait
+            }
+            Ok(_) => {
+                idle_delay = delay;
+                delay
+            }
+            // A batch rolled back once the grace period is over leaves part of the poll
+            // uncommitted, so the cursor stays before it.
+            Err(e) if e.downcast_ref::<Interrupted>().is_some() => {
+                interrupted = true;
+                break;
+            }
+            Err(e) => {
+                error!(
+                    "Watch iteration failed, retrying in {:?}: {:#}",
+                    idle_delay, e
+                );
+                let wait = idle_delay;
+                idle_delay = (idle_delay * 2).min(max_delay);
+                wait
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = follower.shutdown.requested() => {}
+        }
+    }
+
+    warn!(
+        "Watch stopped at created date {} and uidpk {}{}",
+        cursor.created_date,
+        cursor.uidpk,
+        if interrupted {
+            ", the batch in flight was not fully committed"
+        } else {
+            ""
+        }
+    );
+    // Like sync, a watch ended by a stop request exits with `Interrupted`, so dead letters are
+    // only reported.
+    if let Err(e) = follower.dead_letters.check() {
+        warn!("{:#}", e);
+    }
+    Err(Interrupted.into())
+}
+
+/// Imports what source database gained since the cursor, one batch per poll.
+struct Follower<'a> {
+    pools: &'a Pools,
+    args: &'a DatabaseWatchArgs,
+    client_id: &'a str,
+    checkpoint: &'a CheckpointKey,
+    dead_letters: Arc<DeadLetters>,
+    retry: RetryPolicy,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
+    health: Option<Arc<Health>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Follower<'_> {
+    /// Imports the next batch after `cursor` and moves `cursor` past what was committed.
+    /// Returns the number of notifications read from source database.
+    async fn poll(&self, cursor: &mut Cursor) -> Result<usize> {
+        let strategy = self.args.cursor;
+        let (window, filter) = (TimeWindow::default(), ItemFilter::default());
+
+        // A pause window may last hours, so a stop request must not wait for its end.
+        tokio::select! {
+            _ = self.throttle.queries(1) => {}
+            _ = self.shutdown.requested() => return Ok(0),
+        }
+        let keys = self
+            .retry
+            .retry("Fetching the next batch", || {
+                repo::find_raw_keys_after(
+                    &self.pools.source,
+                    strategy,
+                    cursor,
+                    &window,
+                    &filter,
+                    self.args.batch_size,
+                )
+            })
+            .await?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let guids = keys.iter().map(|key| key.guid.clone()).collect::<Vec<_>>();
+        let imported = Import::new(self.pools, self.client_id, 1)
+            .with_checkpoint(self.checkpoint, strategy)
+            .with_conflict_policy(self.args.on_conflict)
+            .with_commit_every(self.args.write.commit_every())
+            .with_dead_letters(&self.dead_letters)
+            .with_retry(self.retry)
+            .with_throttle(&self.throttle)
+            .with_shutdown(&self.shutdown)
+            .with_metrics(&self.metrics)
+            .execute(&guids)
+            .await?;
+
+        // Guids missing from the report were never committed, because a stop cut the batch
+        // short, so the cursor only moves past what is on target database.
+        let committed = if imported.outcomes.len() == guids.len() {
+            keys.iter().map(Cursor::from).collect::<Vec<_>>()
+        } else {
+            imported.raws.iter().map(Cursor::from).collect()
+        };
+        *cursor = cursor.clone().advance(strategy, committed);
+        info!(
+            "Imported a batch of {} notifications, following from created date {} and uidpk {}",
+            keys.len(),
+            cursor.created_date,
+            cursor.uidpk
+        );
+
+        Ok(keys.len())
+    }
+
+    /// Runs `work`, sending a heartbeat every `HEARTBEAT_INTERVAL` until it is done.
+    async fn beating<T>(&self, work: impl Future<Output = T>) -> T {
+        let Some(health) = &self.health else {
+            return work.await;
+        };
+        let mut work = pin!(work);
+        let mut beats = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                done = &mut work => return done,
+                _ = beats.tick() => health.heartbeat(),
+            }
+        }
+    }
+
+    /// Records the outcome of a poll for the health and metrics endpoints, along with the lag
+    /// behind source database when the poll succeeded.
+    async fn report(&self, cursor: &Cursor, polled: &Result<usize>) {
+        let Some(health) = &self.health else {
+            return;
+        };
+        match polled {
+            Ok(_) => {
+                let lag = match repo::get_last_raw_created_date(&self.pools.source).await {
+                    Ok(last) => last.map(|last| last - cursor.created_date),
+                    Err(e) => {
+                        warn!("Could not compute the lag behind source database: {:#}", e);
+                        None
+                    }
+                };
+                self.metrics.set_lag(lag);
+                health.success(cursor, lag);
+            }
+            Err(e) => health.failure(e),
+        }
     }
 }
@@ -1,5 +1,5 @@
 use clap::Parser;
-use clap::{Args, Subcommand};
+use clap::{ArgAction, Args, Subcommand};
 
 use super::config::commands::ConfigCommand;
 use super::database::commands::DatabaseCommand;
@@ -15,8 +15,17 @@ pub struct Cli {
 
 #[derive(Debug, Args)]
 pub struct GlobalOpts {
+    /// Log at debug level at least
     #[clap(short = 'X', long, global = true)]
-    debug: bool,
+    pub debug: bool,
+
+    /// Log one level more, can be repeated
+    #[clap(short = 'v', long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Log one level less, can be repeated
+    #[clap(short = 'q', long, global = true, action = ArgAction::Count)]
+    pub quiet: u8,
 }
 
 #[derive(Debug, Subcommand)]
@@ -25,5 +34,5 @@ pub enum Command {
     Config(ConfigCommand),
 
     /// Database commands
-    Database(DatabaseCommand),
+    Database(Box<DatabaseCommand>),
 }
@@ -0,0 +1,88 @@
+/// MySQL prepared statements accept at most 65535 placeholders.
+pub const MAX_PLACEHOLDERS: usize = 65_535;
+
+/// Values bound in a single `IN (...)` list.
+pub const MAX_IN_VALUES: usize = 1_000;
+
+/// Server default for `max_allowed_packet`, used when it can not be read.
+const DEFAULT_MAX_PACKET_BYTES: usize = 4 * 1024 * 1024;
+
+/// Estimated wire overhead of each bound value, on top of its payload.
+pub const VALUE_OVERHEAD_BYTES: usize = 16;
+
+/// Upper bounds a single multi-row INSERT must stay under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkLimits {
+    pub max_placeholders: usize,
+    pub max_packet_bytes: usize,
+}
+
+impl Default for BulkLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PACKET_BYTES)
+    }
+}
+
+impl BulkLimits {
+    /// Limits for a server with the given `max_allowed_packet`, keeping 10% of headroom.
+    pub fn new(max_allowed_packet: usize) -> Self {
+        Self {
+            max_placeholders: MAX_PLACEHOLDERS,
+            max_packet_bytes: max_allowed_packet / 10 * 9,
+        }
+    }
+
+    /// Splits `rows` into chunks whose statements stay under both limits. `size` estimates the
+    /// bytes a row adds to the statement. A row bigger than the packet budget goes on its own.
+    pub fn split<'a, T>(
+        &self,
+        rows: &'a [T],
+        columns: usize,
+        size: impl Fn(&T) -> usize,
+    ) -> Vec<&'a [T]> {
+        let max_rows = (self.max_placeholders / columns.max(1)).max(1);
+        let mut chunks = vec![];
+        let mut start = 0;
+        let mut bytes = 0;
+
+        for (ix, row) in rows.iter().enumerate() {
+            let row_bytes = size(row) + columns * VALUE_OVERHEAD_BYTES;
+            let len = ix - start;
+            if len > 0 && (len == max_rows || bytes + row_bytes > self.max_packet_bytes) {
+                chunks.push(&rows[start..ix]);
+                start = ix;
+                bytes = 0;
+            }
+            bytes += row_bytes;
+        }
+
+        if start < rows.len() {
+            chunks.push(&rows[start..]);
+        }
+
+        chunks
+    }
+}
+
+/// Payload length of an optional text column.
+pub fn text_len(value: &Option<String>) -> usize {
+    value.as_ref().map(String::len).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let limits = BulkLimits {
+            max_placeholders: 6,
+            max_packet_bytes: 200,
+        };
+        let rows = vec![10, 10, 10, 200, 10];
+
+        let chunks = limits.split(&rows, 2, |r| *r);
+
+        assert_eq!(chunks, vec![&rows[0..3], &rows[3..4], &rows[4..5]]);
+    }
+}
@@ -0,0 +1,177 @@
+use std::cmp::Ordering;
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use sqlx::types::BigDecimal;
+
+use super::models::{RawKey, RawNotification};
+
+/// How the source database is paged while syncing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum CursorStrategy {
+    /// Page by created date only. Notifications sharing the last timestamp of a batch may be skipped
+    CreatedDate,
+
+    /// Page by uidpk only
+    Uidpk,
+
+    /// Page by the (created date, uidpk) keyset
+    #[default]
+    Composite,
+}
+
+impl CursorStrategy {
+    /// Name of the strategy, as given on the command line and stored in checkpoints.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CursorStrategy::CreatedDate => "created-date",
+            CursorStrategy::Uidpk => "uidpk",
+            CursorStrategy::Composite => "composite",
+        }
+    }
+
+    /// Orders two cursors the way this strategy pages the source database.
+    pub fn compare(&self, a: &Cursor, b: &Cursor) -> Ordering {
+        match self {
+            CursorStrategy::CreatedDate => a.created_date.cmp(&b.created_date),
+            CursorStrategy::Uidpk => a.uidpk.cmp(&b.uidpk),
+            CursorStrategy::Composite => {
+                (a.created_date, &a.uidpk).cmp(&(b.created_date, &b.uidpk))
+            }
+        }
+    }
+}
+
+/// Position of the last synced raw notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_date: NaiveDateTime,
+    pub uidpk: BigDecimal,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            created_date: NaiveDateTime::default(),
+            uidpk: BigDecimal::from(0),
+        }
+    }
+}
+
+impl From<&RawNotification> for Cursor {
+    fn from(raw: &RawNotification) -> Self {
+        Self {
+            created_date: raw.created_date,
+            uidpk: raw.uidpk.clone(),
+        }
+    }
+}
+
+impl From<&RawKey> for Cursor {
+    fn from(key: &RawKey) -> Self {
+        Self {
+            created_date: key.created_date,
+            uidpk: key.uidpk.clone(),
+        }
+    }
+}
+
+impl Cursor {
+    pub fn new(created_date: NaiveDateTime, uidpk: BigDecimal) -> Self {
+        Self {
+            created_date,
+            uidpk,
+        }
+    }
+
+    /// Returns the furthest of this cursor and `cursors` according to `strategy`.
+    pub fn advance(
+        self,
+        strategy: CursorStrategy,
+        cursors: impl IntoIterator<Item = Cursor>,
+    ) -> Self {
+        cursors.into_iter().fold(self, |current, next| {
+            match strategy.compare(&next, &current) {
+                Ordering::Greater => next,
+                _ => current,
+            }
+        })
+    }
+}
+
+/// Created date bounds of a sync: `from` inclusive, `to` exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+impl TimeWindow {
+    /// Whether the window limits the sync on either side.
+    pub fn is_bounded(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+}
+
+/// Item attributes a raw notification needs at least one matching item for to be selected.
+/// Empty lists and `None` match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemFilter {
+    pub merchant_accounts: Vec<String>,
+    pub event_codes: Vec<String>,
+    pub payment_methods: Vec<String>,
+    pub currencies: Vec<String>,
+    pub live: Option<bool>,
+    pub success: Option<bool>,
+}
+
+impl ItemFilter {
+    pub fn is_empty(&self) -> bool {
+        self.merchant_accounts.is_empty()
+            && self.event_codes.is_empty()
+            && self.payment_methods.is_empty()
+            && self.currencies.is_empty()
+            && self.live.is_none()
+            && self.success.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(seconds: i64, uidpk: u32) -> Cursor {
+        Cursor::new(
+            NaiveDateTime::from_timestamp_opt(seconds, 0).unwrap(),
+            BigDecimal::from(uidpk),
+        )
+    }
+
+    #[test]
+    fn test_strategy_name() {
+        for strategy in CursorStrategy::value_variants() {
+            assert_eq!(
+                CursorStrategy::from_str(strategy.name(), false).as_ref(),
+                Ok(strategy)
+            );
+        }
+    }
+
+    #[test]
+    fn test_advance() {
+        let batch = vec![cursor(10, 3), cursor(10, 1), cursor(5, 9)];
+
+        assert_eq!(
+            cursor(0, 0).advance(CursorStrategy::Composite, batch.clone()),
+            cursor(10, 3)
+        );
+        assert_eq!(
+            cursor(0, 0).advance(CursorStrategy::Uidpk, batch.clone()),
+            cursor(5, 9)
+        );
+        assert_eq!(
+            cursor(20, 0).advance(CursorStrategy::CreatedDate, batch),
+            cursor(20, 0)
+        );
+    }
+}
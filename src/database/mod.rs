@@ -1,2 +1,7 @@
+pub mod bulk;
+pub mod cursor;
 pub mod models;
+pub mod reconcile;
 pub mod repo;
+pub mod retry;
+pub mod throttle;
@@ -1,6 +1,6 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug};
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDate, NaiveDateTime};
 use sqlx::{types::BigDecimal, FromRow};
 
 pub trait ToTarget {
@@ -43,7 +43,7 @@ impl ToTarget for RawNotification {
     }
 }
 
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone)]
 #[sqlx(rename_all = "UPPERCASE")]
 pub struct RawNotificationHeader {
     pub tadyen_raw_notification_uid: BigDecimal,
@@ -51,7 +51,7 @@ pub struct RawNotificationHeader {
     pub value: Option<String>,
 }
 
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone)]
 #[sqlx(rename_all = "UPPERCASE")]
 pub struct NotificationItem {
     pub uidpk: BigDecimal,
@@ -85,17 +85,191 @@ impl ToTarget for NotificationItem {
     }
 }
 
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone)]
 #[sqlx(rename_all = "UPPERCASE")]
 pub struct NotificationItemOperation {
     pub notification_item_uid: BigDecimal,
     pub operation: String,
 }
 
-#[derive(FromRow, Debug)]
+#[derive(FromRow, Debug, Clone)]
 #[sqlx(rename_all = "UPPERCASE")]
 pub struct NotificationItemData {
     pub notification_item_uid: BigDecimal,
     pub name: String,
     pub value: Option<String>,
 }
+
+/// A raw notification with all of its child rows.
+#[derive(Debug)]
+pub struct NotificationTree {
+    pub raw: RawNotification,
+    pub headers: Vec<RawNotificationHeader>,
+    pub items: Vec<NotificationItemTree>,
+}
+
+#[derive(Debug)]
+pub struct NotificationItemTree {
+    pub item: NotificationItem,
+    pub data: Vec<NotificationItemData>,
+    pub operations: Vec<NotificationItemOperation>,
+}
+
+impl ToTarget for NotificationTree {
+    fn to_target(&mut self, client_id: &str) {
+        self.raw.to_target(client_id);
+        for item in self.items.iter_mut() {
+            item.item.to_target(client_id);
+        }
+    }
+}
+
+impl NotificationTree {
+    /// Number of rows the tree spans across all tables.
+    pub fn rows(&self) -> usize {
+        1 + self.headers.len()
+            + self
+                .items
+                .iter()
+                .map(|item| 1 + item.data.len() + item.operations.len())
+                .sum::<usize>()
+    }
+
+    /// Groups rows fetched for a set of notifications into one tree per raw notification.
+    /// Child rows without a parent in the set are dropped.
+    pub fn assemble(
+        raws: Vec<RawNotification>,
+        headers: Vec<RawNotificationHeader>,
+        items: Vec<NotificationItem>,
+        data: Vec<NotificationItemData>,
+        operations: Vec<NotificationItemOperation>,
+    ) -> Vec<NotificationTree> {
+        let mut headers_by_raw: HashMap<BigDecimal, Vec<RawNotificationHeader>> = HashMap::new();
+        for header in headers {
+            headers_by_raw
+                .entry(header.tadyen_raw_notification_uid.clone())
+                .or_default()
+                .push(header);
+        }
+
+        let mut data_by_item: HashMap<BigDecimal, Vec<NotificationItemData>> = HashMap::new();
+        for row in data {
+            data_by_item
+                .entry(row.notification_item_uid.clone())
+                .or_default()
+                .push(row);
+        }
+
+        let mut operations_by_item: HashMap<BigDecimal, Vec<NotificationItemOperation>> =
+            HashMap::new();
+        for operation in operations {
+            operations_by_item
+                .entry(operation.notification_item_uid.clone())
+                .or_default()
+                .push(operation);
+        }
+
+        let mut items_by_raw: HashMap<String, Vec<NotificationItemTree>> = HashMap::new();
+        for item in items {
+            let tree = NotificationItemTree {
+                data: data_by_item.remove(&item.uidpk).unwrap_or_default(),
+                operations: operations_by_item.remove(&item.uidpk).unwrap_or_default(),
+                item,
+            };
+            items_by_raw
+                .entry(tree.item.raw_notification_item_guid.clone())
+                .or_default()
+                .push(tree);
+        }
+
+        raws.into_iter()
+            .map(|raw| NotificationTree {
+                headers: headers_by_raw.remove(&raw.uidpk).unwrap_or_default(),
+                items: items_by_raw.remove(&raw.guid).unwrap_or_default(),
+                raw,
+            })
+            .collect()
+    }
+}
+
+/// Identifies a sync pipeline: the source and target databases (without credentials)
+/// and the client id written on the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointKey {
+    pub source: String,
+    pub target: String,
+    pub client_id: String,
+}
+
+impl CheckpointKey {
+    pub fn new(source_url: &str, target_url: &str, client_id: &str) -> Self {
+        Self {
+            source: strip_credentials(source_url),
+            target: strip_credentials(target_url),
+            client_id: client_id.to_owned(),
+        }
+    }
+}
+
+fn strip_credentials(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            format!("{}{}", &url[..scheme + 3], &url[at + 1..])
+        }
+        _ => url.to_owned(),
+    }
+}
+
+#[derive(FromRow, Debug, Clone)]
+#[sqlx(rename_all = "UPPERCASE")]
+pub struct SyncCheckpoint {
+    pub source_key: String,
+    pub target_key: String,
+    pub client_id: String,
+    pub last_created_date: NaiveDateTime,
+    pub last_uidpk: BigDecimal,
+    /// Strategy the cursor was saved with, unknown for checkpoints saved before it was recorded
+    /// or set by hand
+    #[sqlx(default)]
+    pub cursor_strategy: Option<String>,
+    pub updated_date: NaiveDateTime,
+}
+
+/// Notifications pending on source database with an item of a merchant account or event code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingGroup {
+    /// Merchant account or event code, `-` for notifications without items
+    pub key: String,
+    pub notifications: i64,
+}
+
+/// Notifications pending on source database created on a day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingDay {
+    pub day: NaiveDate,
+    pub notifications: i64,
+}
+
+/// Notifications created in a time bucket and the sum of their checksums.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumBucket {
+    /// Start of the bucket, formatted as `YYYY-MM-DD HH:MM:SS`
+    pub bucket: String,
+    pub notifications: i64,
+    pub checksum: u64,
+}
+
+/// Checksum of a notification tree over the fields sync does not rewrite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationChecksum {
+    pub guid: String,
+    pub checksum: u64,
+}
+
+/// Paging key of a raw notification, read without its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawKey {
+    pub guid: String,
+    pub created_date: NaiveDateTime,
+    pub uidpk: BigDecimal,
+}
@@ -0,0 +1,2 @@
+ALTER TABLE adyen_sync_checkpoint
+    ADD COLUMN CURSOR_STRATEGY VARCHAR(32) NULL AFTER LAST_UIDPK;
@@ -0,0 +1,5 @@
+select count(1)
+from information_schema.columns
+where table_schema = database()
+    and table_name = 'adyen_sync_checkpoint'
+    and column_name = 'cursor_strategy';
@@ -0,0 +1,3 @@
+select count(1)
+from information_schema.tables
+where table_schema = database() and table_name = 'adyen_sync_checkpoint';
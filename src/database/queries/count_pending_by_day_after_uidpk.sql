@@ -0,0 +1,13 @@
+select date(r.created_date) as day,
+    count(1) as notifications
+from tadyen_raw_notification r
+where r.uidpk > ?
+    and (? is null or r.created_date >= ?)
+    and (? is null or r.created_date < ?)
+    and (? = 0 or exists (
+        select 1
+        from tadyen_notification_item i
+        where i.raw_notification_item_guid = r.guid
+            /* item filter */))
+group by day
+order by day;
@@ -0,0 +1,10 @@
+select coalesce(i.event_code, '-') as event_code,
+    count(distinct r.uidpk) as notifications
+from tadyen_raw_notification r
+left join tadyen_notification_item i on i.raw_notification_item_guid = r.guid
+where (r.created_date > ?
+        or (r.created_date = ? and r.uidpk > ?))
+    and (? is null or r.created_date >= ?)
+    and (? is null or r.created_date < ?)
+    /* item filter */
+group by event_code;
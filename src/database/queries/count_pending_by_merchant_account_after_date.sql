@@ -0,0 +1,9 @@
+select coalesce(i.merchant_account_code, '-') as merchant_account_code,
+    count(distinct r.uidpk) as notifications
+from tadyen_raw_notification r
+left join tadyen_notification_item i on i.raw_notification_item_guid = r.guid
+where r.created_date > ?
+    and (? is null or r.created_date >= ?)
+    and (? is null or r.created_date < ?)
+    /* item filter */
+group by merchant_account_code;
@@ -0,0 +1,6 @@
+select count(1)
+from tadyen_raw_notification 
+where (created_date > ? 
+        or (created_date = ? and uidpk > ?))
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?);
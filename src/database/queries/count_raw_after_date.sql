@@ -1,3 +1,5 @@
 select count(1)
 from tadyen_raw_notification 
-where created_date > ?;
+where created_date > ?
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?);
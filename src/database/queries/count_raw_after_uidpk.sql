@@ -0,0 +1,5 @@
+select count(1)
+from tadyen_raw_notification 
+where uidpk > ?
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?);
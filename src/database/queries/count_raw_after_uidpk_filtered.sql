@@ -0,0 +1,10 @@
+select count(1)
+from tadyen_raw_notification r
+where r.uidpk > ?
+    and (? is null or r.created_date >= ?)
+    and (? is null or r.created_date < ?)
+    and exists (
+        select 1
+        from tadyen_notification_item i
+        where i.raw_notification_item_guid = r.guid
+            /* item filter */);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS adyen_sync_checkpoint (
+    SOURCE_KEY VARCHAR(255) NOT NULL,
+    TARGET_KEY VARCHAR(255) NOT NULL,
+    CLIENT_ID VARCHAR(255) NOT NULL,
+    LAST_CREATED_DATE DATETIME(6) NOT NULL,
+    LAST_UIDPK DECIMAL(19,0) NOT NULL,
+    CURSOR_STRATEGY VARCHAR(32) NULL,
+    UPDATED_DATE DATETIME(6) NOT NULL,
+    PRIMARY KEY (SOURCE_KEY, TARGET_KEY, CLIENT_ID)
+);
@@ -0,0 +1,2 @@
+delete from adyen_sync_checkpoint
+where source_key = ? and target_key = ? and client_id = ?;
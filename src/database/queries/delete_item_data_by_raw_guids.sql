@@ -0,0 +1,4 @@
+delete d
+from tadyen_notification_data d
+join tadyen_notification_item i on i.uidpk = d.notification_item_uid
+where i.raw_notification_item_guid in 
@@ -0,0 +1,4 @@
+delete o
+from tadyen_notification_operation o
+join tadyen_notification_item i on i.uidpk = o.notification_item_uid
+where i.raw_notification_item_guid in 
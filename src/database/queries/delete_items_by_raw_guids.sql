@@ -0,0 +1,2 @@
+delete from tadyen_notification_item
+where raw_notification_item_guid in 
@@ -0,0 +1,4 @@
+delete h
+from tadyen_raw_notif_header h
+join tadyen_raw_notification r on r.uidpk = h.tadyen_raw_notification_uid
+where r.guid in 
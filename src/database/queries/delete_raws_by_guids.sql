@@ -0,0 +1,2 @@
+delete from tadyen_raw_notification
+where guid in 
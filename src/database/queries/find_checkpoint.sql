@@ -0,0 +1,3 @@
+select *
+from adyen_sync_checkpoint
+where source_key = ? and target_key = ? and client_id = ?;
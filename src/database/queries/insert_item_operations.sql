@@ -1,2 +1 @@
 INSERT INTO tadyen_notification_operation (NOTIFICATION_ITEM_UID,OPERATION) 
-VALUES(?,?);
@@ -18,5 +18,4 @@ INSERT INTO tadyen_notification_item (
     LIVE,
     ORIGINAL_REFERENCE,
     CLIENT_ID,
-    RAW_NOTIFICATION_ITEM_GUID) VALUES
-(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);
+    RAW_NOTIFICATION_ITEM_GUID) 
@@ -1,2 +1 @@
 INSERT INTO tadyen_notification_data (NOTIFICATION_ITEM_UID,NAME,VALUE) 
-VALUES(?,?,?);
@@ -1,2 +1 @@
 INSERT INTO tadyen_raw_notif_header (TADYEN_RAW_NOTIFICATION_UID,NAME,VALUE) 
-VALUES (?,?,?);
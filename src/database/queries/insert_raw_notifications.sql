@@ -7,4 +7,3 @@ INSERT INTO tadyen_raw_notification (
         BODY,
         CLIENT_ID,
         CONSUME_SUCCESS) 
-    VALUES (?,?,?,?,?,?,?,?)
\ No newline at end of file
@@ -0,0 +1,34 @@
+select n.bucket,
+    count(1) as notifications,
+    cast(sum(n.checksum) as unsigned) as checksum
+from (
+    select date_format(r.created_date, ?) as bucket,
+        crc32(concat_ws('|', r.uidpk, r.guid, r.created_date, md5(coalesce(r.body, '\\N')),
+            (select coalesce(sum(crc32(concat_ws('|', h.name, coalesce(h.value, '\\N')))), 0)
+                from tadyen_raw_notif_header h
+                where h.tadyen_raw_notification_uid = r.uidpk),
+            (select coalesce(sum(crc32(concat_ws('|', i.uidpk, i.guid, i.created_date,
+                    coalesce(i.currency, '\\N'), coalesce(i.amount, '\\N'), i.event_code,
+                    coalesce(i.event_date, '\\N'), i.merchant_account_code, i.merchant_reference,
+                    coalesce(i.payment_method, '\\N'), i.psp_reference, coalesce(i.reason, '\\N'),
+                    i.success, i.live, coalesce(i.original_reference, '\\N'),
+                    i.raw_notification_item_guid,
+                    (select coalesce(sum(crc32(concat_ws('|', d.name, coalesce(d.value, '\\N')))), 0)
+                        from tadyen_notification_data d
+                        where d.notification_item_uid = i.uidpk),
+                    (select coalesce(sum(crc32(o.operation)), 0)
+                        from tadyen_notification_operation o
+                        where o.notification_item_uid = i.uidpk)))), 0)
+                from tadyen_notification_item i
+                where i.raw_notification_item_guid = r.guid))) as checksum
+    from tadyen_raw_notification r
+    where (? is null or r.created_date >= ?)
+        and (? is null or r.created_date < ?)
+        and (? = 0 or exists (
+            select 1
+            from tadyen_notification_item i
+            where i.raw_notification_item_guid = r.guid
+                /* item filter */))
+) n
+group by n.bucket
+order by n.bucket;
@@ -0,0 +1,3 @@
+select guid
+from tadyen_raw_notification
+where guid in 
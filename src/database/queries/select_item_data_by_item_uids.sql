@@ -0,0 +1,3 @@
+select *
+from tadyen_notification_data
+where notification_item_uid in 
@@ -0,0 +1,3 @@
+select *
+from tadyen_notification_operation
+where notification_item_uid in 
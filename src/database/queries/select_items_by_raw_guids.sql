@@ -0,0 +1,3 @@
+select *
+from tadyen_notification_item
+where raw_notification_item_guid in 
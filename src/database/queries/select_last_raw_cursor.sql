@@ -0,0 +1,4 @@
+select created_date, uidpk
+from tadyen_raw_notification
+order by created_date desc, uidpk desc
+limit 1;
@@ -1 +1,4 @@
-select coalesce(max(uidpk), 0) from tadyen_raw_notification
\ No newline at end of file
+select created_date, uidpk
+from tadyen_raw_notification
+order by uidpk desc
+limit 1;
@@ -0,0 +1,8 @@
+select guid, created_date, uidpk
+from tadyen_raw_notification 
+where (created_date > ? 
+        or (created_date = ? and uidpk > ?))
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?)
+order by created_date asc, uidpk asc 
+limit ?;
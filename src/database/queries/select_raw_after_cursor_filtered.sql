@@ -0,0 +1,13 @@
+select r.guid, r.created_date, r.uidpk
+from tadyen_raw_notification r
+where (r.created_date > ?
+        or (r.created_date = ? and r.uidpk > ?))
+    and (? is null or r.created_date >= ?)
+    and (? is null or r.created_date < ?)
+    and exists (
+        select 1
+        from tadyen_notification_item i
+        where i.raw_notification_item_guid = r.guid
+            /* item filter */)
+order by r.created_date asc, r.uidpk asc
+limit ?;
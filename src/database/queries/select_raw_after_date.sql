@@ -1,5 +1,7 @@
-select guid
+select guid, created_date, uidpk
 from tadyen_raw_notification 
 where created_date > ? 
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?)
 order by created_date asc 
-limit ?;
\ No newline at end of file
+limit ?;
@@ -0,0 +1,3 @@
+select *
+from tadyen_raw_notif_header h
+where h.tadyen_raw_notification_uid in 
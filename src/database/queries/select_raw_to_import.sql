@@ -1,5 +1,7 @@
-select * 
+select guid, created_date, uidpk
 from tadyen_raw_notification 
 where uidpk > ? 
+    and (? is null or created_date >= ?)
+    and (? is null or created_date < ?)
 order by uidpk asc 
-limit ?;
\ No newline at end of file
+limit ?;
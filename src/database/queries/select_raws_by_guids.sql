@@ -0,0 +1,3 @@
+select *
+from tadyen_raw_notification
+where guid in 
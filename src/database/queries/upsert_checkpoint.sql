@@ -0,0 +1,14 @@
+INSERT INTO adyen_sync_checkpoint (
+        SOURCE_KEY,
+        TARGET_KEY,
+        CLIENT_ID,
+        LAST_CREATED_DATE,
+        LAST_UIDPK,
+        CURSOR_STRATEGY,
+        UPDATED_DATE)
+    VALUES (?,?,?,?,?,?,UTC_TIMESTAMP(6))
+ON DUPLICATE KEY UPDATE
+    LAST_CREATED_DATE = VALUES(LAST_CREATED_DATE),
+    LAST_UIDPK = VALUES(LAST_UIDPK),
+    CURSOR_STRATEGY = VALUES(CURSOR_STRATEGY),
+    UPDATED_DATE = VALUES(UPDATED_DATE);
@@ -0,0 +1,628 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cursor::TimeWindow,
+    models::{
+        ChecksumBucket, NotificationChecksum, NotificationItem, NotificationItemTree,
+        NotificationTree, RawNotification, RawNotificationHeader,
+    },
+};
+
+/// Span of the created dates whose checksums are summed together by a fast verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BucketSize {
+    #[default]
+    Day,
+    Hour,
+    /// Finest bucket, only reached by drilling down
+    #[value(skip)]
+    Minute,
+}
+
+impl BucketSize {
+    /// MySQL `date_format` pattern truncating a created date to the start of its bucket.
+    pub fn format(&self) -> &'static str {
+        match self {
+            BucketSize::Day => "%Y-%m-%d 00:00:00",
+            BucketSize::Hour => "%Y-%m-%d %H:00:00",
+            BucketSize::Minute => "%Y-%m-%d %H:%i:00",
+        }
+    }
+
+    /// The next smaller bucket size, `None` once buckets are split into notifications.
+    pub fn finer(&self) -> Option<BucketSize> {
+        match self {
+            BucketSize::Day => Some(BucketSize::Hour),
+            BucketSize::Hour => Some(BucketSize::Minute),
+            BucketSize::Minute => None,
+        }
+    }
+
+    /// Created dates of `bucket`, as returned by `find_checksum_buckets`, kept inside `window`.
+    pub fn window(&self, bucket: &str, window: &TimeWindow) -> Result<TimeWindow> {
+        let start = NaiveDateTime::parse_from_str(bucket, "%Y-%m-%d %H:%M:%S")
+            .with_context(|| format!("Invalid checksum bucket: {}", bucket))?;
+        let end = start
+            + match self {
+                BucketSize::Day => Duration::days(1),
+                BucketSize::Hour => Duration::hours(1),
+                BucketSize::Minute => Duration::minutes(1),
+            };
+        Ok(TimeWindow {
+            from: Some(window.from.map_or(start, |from| from.max(start))),
+            to: Some(window.to.map_or(end, |to| to.min(end))),
+        })
+    }
+}
+
+/// Buckets whose notification count or checksum differ between both sides, in bucket order.
+pub fn differing_buckets(source: &[ChecksumBucket], target: &[ChecksumBucket]) -> Vec<String> {
+    let key = |bucket: &ChecksumBucket| (bucket.notifications, bucket.checksum);
+    let source = source
+        .iter()
+        .map(|bucket| (bucket.bucket.as_str(), key(bucket)))
+        .collect::<BTreeMap<_, _>>();
+    let target = target
+        .iter()
+        .map(|bucket| (bucket.bucket.as_str(), key(bucket)))
+        .collect::<BTreeMap<_, _>>();
+    source
+        .keys()
+        .chain(target.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|bucket| source.get(*bucket) != target.get(*bucket))
+        .map(|bucket| bucket.to_string())
+        .collect()
+}
+
+/// Guids whose checksums differ between both sides.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChecksumDiff {
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+    pub differs: Vec<String>,
+}
+
+impl ChecksumDiff {
+    pub fn new(source: &[NotificationChecksum], target: &[NotificationChecksum]) -> Self {
+        let mut diff = Self::default();
+        let mut target = target
+            .iter()
+            .map(|row| (row.guid.as_str(), row.checksum))
+            .collect::<HashMap<_, _>>();
+        for row in source {
+            match target.remove(row.guid.as_str()) {
+                None => diff.missing.push(row.guid.clone()),
+                Some(checksum) if checksum != row.checksum => diff.differs.push(row.guid.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.extra = target.into_keys().map(str::to_owned).collect();
+        diff.extra.sort();
+        diff
+    }
+}
+
+/// How a notification differs between source and target databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// On source database only
+    Missing,
+    /// On target database only
+    Extra,
+    /// On both, with different rows
+    Differs,
+}
+
+/// A field whose value differs. `None` means the row holding it does not exist on that side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldDiff {
+    pub field: String,
+    pub source: Option<String>,
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mismatch {
+    pub guid: String,
+    pub kind: MismatchKind,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diffs: Vec<FieldDiff>,
+}
+
+impl Mismatch {
+    pub fn new(guid: &str, kind: MismatchKind) -> Self {
+        Self {
+            guid: guid.to_owned(),
+            kind,
+            diffs: vec![],
+        }
+    }
+}
+
+/// Outcome of comparing notifications between source and target databases.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// Notifications found on at least one side
+    pub checked: usize,
+    pub matched: usize,
+    /// Listed guids found on neither side
+    #[serde(default)]
+    pub not_found: Vec<String>,
+    #[serde(default)]
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    /// Compares the trees fetched from both sides for `guids`, in `guids` order.
+    pub fn add(
+        &mut self,
+        guids: &[String],
+        source: Vec<NotificationTree>,
+        target: Vec<NotificationTree>,
+    ) {
+        let mut source = by_guid(source);
+        let mut target = by_guid(target);
+        for guid in guids {
+            let mismatch = match (source.remove(guid), target.remove(guid)) {
+                (None, None) => {
+                    self.not_found.push(guid.clone());
+                    continue;
+                }
+                (Some(_), None) => Mismatch::new(guid, MismatchKind::Missing),
+                (None, Some(_)) => Mismatch::new(guid, MismatchKind::Extra),
+                (Some(source), Some(target)) => Mismatch {
+                    diffs: compare(&source, &target),
+                    ..Mismatch::new(guid, MismatchKind::Differs)
+                },
+            };
+            self.checked += 1;
+            if mismatch.kind == MismatchKind::Differs && mismatch.diffs.is_empty() {
+                self.matched += 1;
+            } else {
+                self.mismatches.push(mismatch);
+            }
+        }
+    }
+
+    /// Records target notifications whose guid is not on source database.
+    pub fn extra(&mut self, guids: impl IntoIterator<Item = String>) {
+        for guid in guids {
+            self.checked += 1;
+            self.mismatches
+                .push(Mismatch::new(&guid, MismatchKind::Extra));
+        }
+    }
+}
+
+fn by_guid(trees: Vec<NotificationTree>) -> BTreeMap<String, NotificationTree> {
+    trees
+        .into_iter()
+        .map(|tree| (tree.raw.guid.clone(), tree))
+        .collect()
+}
+
+/// Fields of raw notifications and items that `ToTarget` rewrites on their way to target
+/// database.
+pub const REWRITTEN_FIELDS: [&str; 4] =
+    ["client_id", "consumed", "consumed_date", "consume_success"];
+
+/// Compares a source tree with its copy on target database, ignoring `REWRITTEN_FIELDS`.
+pub fn compare(source: &NotificationTree, target: &NotificationTree) -> Vec<FieldDiff> {
+    let mut diffs = vec![];
+    compare_fields(
+        "raw",
+        &raw_fields(&source.raw),
+        &raw_fields(&target.raw),
+        &mut diffs,
+    );
+    compare_values(
+        "headers",
+        &headers(&source.headers),
+        &headers(&target.headers),
+        &mut diffs,
+    );
+
+    let source_items = items(&source.items);
+    let target_items = items(&target.items);
+    for guid in source_items
+        .keys()
+        .chain(target_items.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        let path = format!("items[{}]", guid);
+        match (source_items.get(guid), target_items.get(guid)) {
+            (Some(source), Some(target)) => compare_item(&path, source, target, &mut diffs),
+            (source, target) => diffs.push(FieldDiff {
+                field: path,
+                source: source.map(|_| "present".to_owned()),
+                target: target.map(|_| "present".to_owned()),
+            }),
+        }
+    }
+
+    diffs
+}
+
+fn compare_item(
+    path: &str,
+    source: &NotificationItemTree,
+    target: &NotificationItemTree,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    compare_fields(
+        path,
+        &item_fields(&source.item),
+        &item_fields(&target.item),
+        diffs,
+    );
+
+    let data = |tree: &NotificationItemTree| {
+        group(
+            tree.data
+                .iter()
+                .map(|row| (row.name.clone(), value(&row.value))),
+        )
+    };
+    compare_values(
+        &format!("{}.data", path),
+        &data(source),
+        &data(target),
+        diffs,
+    );
+
+    let operations = |tree: &NotificationItemTree| {
+        group(
+            tree.operations
+                .iter()
+                .map(|row| (row.operation.clone(), "present".to_owned())),
+        )
+    };
+    compare_values(
+        &format!("{}.operations", path),
+        &operations(source),
+        &operations(target),
+        diffs,
+    );
+}
+
+/// Compares fields of two rows known to exist on both sides.
+fn compare_fields(
+    path: &str,
+    source: &[(&'static str, String)],
+    target: &[(&'static str, String)],
+    diffs: &mut Vec<FieldDiff>,
+) {
+    for ((name, source), (_, target)) in source.iter().zip(target) {
+        if source != target && !REWRITTEN_FIELDS.contains(name) {
+            diffs.push(FieldDiff {
+                field: format!("{}.{}", path, name),
+                source: Some(source.clone()),
+                target: Some(target.clone()),
+            });
+        }
+    }
+}
+
+/// Compares child rows grouped by name, where a name may hold several rows.
+fn compare_values(
+    path: &str,
+    source: &BTreeMap<String, Vec<String>>,
+    target: &BTreeMap<String, Vec<String>>,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    for name in source.keys().chain(target.keys()).collect::<BTreeSet<_>>() {
+        let source = source.get(name).map(|values| values.join(", "));
+        let target = target.get(name).map(|values| values.join(", "));
+        if source != target {
+            diffs.push(FieldDiff {
+                field: format!("{}[{}]", path, name),
+                source,
+                target,
+            });
+        }
+    }
+}
+
+/// Every stored field of `raw` by name.
+pub fn raw_fields(raw: &RawNotification) -> Vec<(&'static str, String)> {
+    vec![
+        ("uidpk", raw.uidpk.to_string()),
+        ("guid", raw.guid.clone()),
+        ("created_date", raw.created_date.to_string()),
+        ("consumed", raw.consumed.to_string()),
+        ("consumed_date", value(&raw.consumed_date)),
+        ("consume_success", raw.consume_success.to_string()),
+        ("client_id", raw.client_id.clone()),
+        ("body", value(&raw.body)),
+    ]
+}
+
+/// Every stored field of `item` by name, but its guid.
+pub fn item_fields(item: &NotificationItem) -> Vec<(&'static str, String)> {
+    vec![
+        ("uidpk", item.uidpk.to_string()),
+        ("created_date", item.created_date.to_string()),
+        ("consumed", item.consumed.to_string()),
+        ("consumed_date", value(&item.consumed_date)),
+        ("consume_success", item.consume_success.to_string()),
+        ("client_id", item.client_id.clone()),
+        ("currency", value(&item.currency)),
+        ("amount", value(&item.amount)),
+        ("event_code", item.event_code.clone()),
+        ("event_date", value(&item.event_date)),
+        ("merchant_account_code", item.merchant_account_code.clone()),
+        ("merchant_reference", item.merchant_reference.clone()),
+        ("payment_method", value(&item.payment_method)),
+        ("psp_reference", item.psp_reference.clone()),
+        ("reason", value(&item.reason)),
+        ("success", item.success.to_string()),
+        ("live", item.live.to_string()),
+        ("original_reference", value(&item.original_reference)),
+        (
+            "raw_notification_item_guid",
+            item.raw_notification_item_guid.clone(),
+        ),
+    ]
+}
+
+fn headers(headers: &[RawNotificationHeader]) -> BTreeMap<String, Vec<String>> {
+    group(
+        headers
+            .iter()
+            .map(|header| (header.name.clone(), value(&header.value))),
+    )
+}
+
+fn items(items: &[NotificationItemTree]) -> BTreeMap<&str, &NotificationItemTree> {
+    items
+        .iter()
+        .map(|tree| (tree.item.guid.as_str(), tree))
+        .collect()
+}
+
+/// Groups values by name, sorted so row order does not matter.
+pub fn group(rows: impl Iterator<Item = (String, String)>) -> BTreeMap<String, Vec<String>> {
+    let mut grouped = BTreeMap::<String, Vec<String>>::new();
+    for (name, value) in rows {
+        grouped.entry(name).or_default().push(value);
+    }
+    grouped.values_mut().for_each(|values| values.sort());
+    grouped
+}
+
+/// Displays an optional column, `NULL` when it is not set.
+pub fn value<T: Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "NULL".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::{NotificationItemData, NotificationItemOperation, ToTarget};
+    use chrono::NaiveDate;
+    use sqlx::types::BigDecimal;
+
+    fn tree() -> NotificationTree {
+        let created_date = NaiveDate::from_ymd_opt(2023, 3, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        NotificationTree {
+            raw: RawNotification {
+                uidpk: BigDecimal::from(1),
+                guid: "raw".to_owned(),
+                created_date,
+                consumed_date: Some(created_date),
+                consumed: BigDecimal::from(1),
+                body: Some("{}".to_owned()),
+                client_id: "source".to_owned(),
+                consume_success: BigDecimal::from(1),
+            },
+            headers: vec![RawNotificationHeader {
+                tadyen_raw_notification_uid: BigDecimal::from(1),
+                name: "content-type".to_owned(),
+                value: Some("application/json".to_owned()),
+            }],
+            items: vec![NotificationItemTree {
+                item: NotificationItem {
+                    uidpk: BigDecimal::from(2),
+                    guid: "item".to_owned(),
+                    created_date,
+                    consume_success: BigDecimal::from(1),
+                    consumed_date: None,
+                    consumed: BigDecimal::from(1),
+                    currency: Some("EUR".to_owned()),
+                    amount: Some(BigDecimal::from(100)),
+                    event_code: "AUTHORISATION".to_owned(),
+                    event_date: None,
+                    merchant_account_code: "shop".to_owned(),
+                    merchant_reference: "order".to_owned(),
+                    payment_method: None,
+                    psp_reference: "psp".to_owned(),
+                    reason: None,
+                    success: BigDecimal::from(1),
+                    live: BigDecimal::from(0),
+                    original_reference: None,
+                    client_id: "source".to_owned(),
+                    raw_notification_item_guid: "raw".to_owned(),
+                },
+                data: vec![NotificationItemData {
+                    notification_item_uid: BigDecimal::from(2),
+                    name: "authCode".to_owned(),
+                    value: Some("1234".to_owned()),
+                }],
+                operations: vec![NotificationItemOperation {
+                    notification_item_uid: BigDecimal::from(2),
+                    operation: "CAPTURE".to_owned(),
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_compare_ignores_rewritten_fields() {
+        let mut target = tree();
+        target.to_target("target");
+        assert!(compare(&tree(), &target).is_empty());
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut target = tree();
+        target.raw.body = None;
+        target.items[0].item.amount = Some(BigDecimal::from(200));
+        target.items[0].data.clear();
+        target.items[0].operations.push(NotificationItemOperation {
+            notification_item_uid: BigDecimal::from(2),
+            operation: "REFUND".to_owned(),
+        });
+
+        let field = |field: &str, source: Option<&str>, target: Option<&str>| FieldDiff {
+            field: field.to_owned(),
+            source: source.map(str::to_owned),
+            target: target.map(str::to_owned),
+        };
+        assert_eq!(
+            compare(&tree(), &target),
+            vec![
+                field("raw.body", Some("{}"), Some("NULL")),
+                field("items[item].amount", Some("100"), Some("200")),
+                field("items[item].data[authCode]", Some("1234"), None),
+                field("items[item].operations[REFUND]", None, Some("present")),
+            ]
+        );
+    }
+
+    fn bucket(bucket: &str, notifications: i64, checksum: u64) -> ChecksumBucket {
+        ChecksumBucket {
+            bucket: bucket.to_owned(),
+            notifications,
+            checksum,
+        }
+    }
+
+    fn checksum(guid: &str, checksum: u64) -> NotificationChecksum {
+        NotificationChecksum {
+            guid: guid.to_owned(),
+            checksum,
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        let source = [
+            bucket("2023-03-01 00:00:00", 2, 10),
+            bucket("2023-03-02 00:00:00", 1, 5),
+            bucket("2023-03-03 00:00:00", 1, 7),
+        ];
+        let target = [
+            bucket("2023-03-01 00:00:00", 2, 10),
+            bucket("2023-03-02 00:00:00", 1, 6),
+            bucket("2023-03-04 00:00:00", 1, 3),
+        ];
+        assert_eq!(
+            differing_buckets(&source, &target),
+            vec![
+                "2023-03-02 00:00:00",
+                "2023-03-03 00:00:00",
+                "2023-03-04 00:00:00"
+            ]
+        );
+
+        let diff = ChecksumDiff::new(
+            &[
+                checksum("same", 1),
+                checksum("changed", 2),
+                checksum("missing", 3),
+            ],
+            &[
+                checksum("same", 1),
+                checksum("changed", 4),
+                checksum("extra", 5),
+            ],
+        );
+        assert_eq!(diff.missing, vec!["missing"]);
+        assert_eq!(diff.extra, vec!["extra"]);
+        assert_eq!(diff.differs, vec!["changed"]);
+    }
+
+    #[test]
+    fn test_bucket_window() {
+        let date = |day, hour| {
+            NaiveDate::from_ymd_opt(2023, 3, day)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+        };
+        let window = TimeWindow {
+            from: date(1, 10),
+            to: None,
+        };
+        assert_eq!(
+            BucketSize::Day
+                .window("2023-03-01 00:00:00", &window)
+                .unwrap(),
+            TimeWindow {
+                from: date(1, 10),
+                to: date(2, 0),
+            }
+        );
+        assert_eq!(
+            BucketSize::Hour
+                .window("2023-03-01 11:00:00", &window)
+                .unwrap(),
+            TimeWindow {
+                from: date(1, 11),
+                to: date(1, 12),
+            }
+        );
+        assert_eq!(BucketSize::Hour.finer(), Some(BucketSize::Minute));
+        assert!(BucketSize::Minute.finer().is_none());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut other = tree();
+        other.raw.guid = "other".to_owned();
+        let mut differs = tree();
+        differs.raw.guid = "differs".to_owned();
+        let mut changed = tree();
+        changed.raw.guid = "differs".to_owned();
+        changed.headers.clear();
+
+        let guids = ["raw", "other", "differs", "unknown"].map(str::to_owned);
+        let mut report = VerifyReport::default();
+        report.add(&guids, vec![tree(), other, differs], vec![tree(), changed]);
+        report.extra(vec!["extra".to_owned()]);
+
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.matched, 1);
+        assert_eq!(report.not_found, vec!["unknown".to_owned()]);
+        let kinds = report
+            .mismatches
+            .iter()
+            .map(|mismatch| (mismatch.guid.as_str(), mismatch.kind, mismatch.diffs.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("other", MismatchKind::Missing, 0),
+                ("differs", MismatchKind::Differs, 1),
+                ("extra", MismatchKind::Extra, 0),
+            ]
+        );
+    }
+}
@@ -1,24 +1,55 @@
+use super::bulk::{text_len, BulkLimits, MAX_IN_VALUES};
+use super::cursor::{Cursor, CursorStrategy, ItemFilter, TimeWindow};
 use super::models::{
-    NotificationItem, NotificationItemData, NotificationItemOperation, RawNotification,
-    RawNotificationHeader,
+    CheckpointKey, ChecksumBucket, NotificationChecksum, NotificationItem, NotificationItemData,
+    NotificationItemOperation, NotificationItemTree, NotificationTree, PendingDay, PendingGroup,
+    RawKey, RawNotification, RawNotificationHeader, SyncCheckpoint,
 };
 use crate::commands::database::commands::{
-    CommonsDatabaseArgs, DatabaseStatusArgs, DatabaseSyncArgs, DatabaseWatchArgs,
+    CommonsDatabaseArgs, DatabaseCheckpointArgs, DatabaseInspectArgs, DatabaseRepairArgs,
+    DatabaseStatusArgs, DatabaseSyncArgs, DatabaseVerifyArgs, DatabaseWatchArgs,
 };
 use anyhow::{Context, Result};
-use chrono::NaiveDateTime;
-use sqlx::{mysql::MySqlPoolOptions, types::BigDecimal, MySql, MySqlExecutor, MySqlPool};
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{
+    mysql::{MySqlArguments, MySqlPoolOptions, MySqlRow},
+    query::QueryAs,
+    query_builder::Separated,
+    types::BigDecimal,
+    Encode, FromRow, MySql, MySqlConnection, MySqlExecutor, MySqlPool, QueryBuilder, Type,
+};
 use std::time::Duration;
 
 const SELECT_LAST_RAW_CREATED_DATE_QUERY: &str =
     include_str!("queries/select_last_raw_created_date_uidpk.sql");
+const SELECT_LAST_RAW_CURSOR_QUERY: &str = include_str!("queries/select_last_raw_cursor.sql");
+const SELECT_LAST_RAW_UIDPK_QUERY: &str = include_str!("queries/select_last_raw_uidpk.sql");
+
+const BULK_INSERT_RAW_QUERY: &str = include_str!("queries/insert_raw_notifications.sql");
+const BULK_INSERT_RAW_HEADER_QUERY: &str =
+    include_str!("queries/insert_raw_notification_headers.sql");
+const BULK_INSERT_ITEM_QUERY: &str = include_str!("queries/insert_items.sql");
+const BULK_INSERT_ITEM_DATA_QUERY: &str = include_str!("queries/insert_items_data.sql");
+const BULK_INSERT_ITEM_OPERATION_QUERY: &str = include_str!("queries/insert_item_operations.sql");
 
-const INSERT_RAW_QUERY: &str = include_str!("queries/insert_raw_notification.sql");
-const INSERT_RAW_HEADER_QUERY: &str = include_str!("queries/insert_raw_notification_header.sql");
+const SELECT_RAWS_BY_GUIDS_QUERY: &str = include_str!("queries/select_raws_by_guids.sql");
+const SELECT_EXISTING_RAW_GUIDS_QUERY: &str = include_str!("queries/select_existing_raw_guids.sql");
+const SELECT_RAW_HEADERS_BY_RAW_UIDS_QUERY: &str =
+    include_str!("queries/select_raw_headers_by_raw_uids.sql");
+const SELECT_ITEMS_BY_RAW_GUIDS_QUERY: &str = include_str!("queries/select_items_by_raw_guids.sql");
+const SELECT_ITEM_DATA_BY_ITEM_UIDS_QUERY: &str =
+    include_str!("queries/select_item_data_by_item_uids.sql");
+const SELECT_ITEM_OPERATIONS_BY_ITEM_UIDS_QUERY: &str =
+    include_str!("queries/select_item_operations_by_item_uids.sql");
 
-const INSERT_ITEM_QUERY: &str = include_str!("queries/insert_item.sql");
-const INSERT_ITEM_DATA_QUERY: &str = include_str!("queries/insert_item_data.sql");
-const INSERT_ITEM_OPERATION_QUERY: &str = include_str!("queries/insert_item_operation.sql");
+const DELETE_ITEM_OPERATIONS_BY_RAW_GUIDS_QUERY: &str =
+    include_str!("queries/delete_item_operations_by_raw_guids.sql");
+const DELETE_ITEM_DATA_BY_RAW_GUIDS_QUERY: &str =
+    include_str!("queries/delete_item_data_by_raw_guids.sql");
+const DELETE_ITEMS_BY_RAW_GUIDS_QUERY: &str = include_str!("queries/delete_items_by_raw_guids.sql");
+const DELETE_RAW_HEADERS_BY_RAW_GUIDS_QUERY: &str =
+    include_str!("queries/delete_raw_headers_by_raw_guids.sql");
+const DELETE_RAWS_BY_GUIDS_QUERY: &str = include_str!("queries/delete_raws_by_guids.sql");
 
 const SELECT_RAW_TO_IMPORT_QUERY: &str = include_str!("queries/select_raw_to_import.sql");
 const SELECT_RAW_HEADERS_QUERY: &str = include_str!("queries/select_raw_headers.sql");
@@ -27,15 +58,61 @@ const SELECT_ITEMS_QUERY: &str = include_str!("queries/select_items.sql");
 const SELECT_ITEM_DATA_QUERY: &str = include_str!("queries/select_item_data.sql");
 const SELECT_ITEM_OPERATIONS_QUERY: &str = include_str!("queries/select_item_operations.sql");
 const SELECT_RAW_AFTER_DATE_QUERY: &str = include_str!("queries/select_raw_after_date.sql");
+const SELECT_RAW_AFTER_CURSOR_QUERY: &str = include_str!("queries/select_raw_after_cursor.sql");
+const SELECT_RAW_AFTER_DATE_FILTERED_QUERY: &str =
+    include_str!("queries/select_raw_after_date_filtered.sql");
+const SELECT_RAW_AFTER_UIDPK_FILTERED_QUERY: &str =
+    include_str!("queries/select_raw_after_uidpk_filtered.sql");
+const SELECT_RAW_AFTER_CURSOR_FILTERED_QUERY: &str =
+    include_str!("queries/select_raw_after_cursor_filtered.sql");
+const COUNT_RAW_AFTER_DATE_FILTERED_QUERY: &str =
+    include_str!("queries/count_raw_after_date_filtered.sql");
+const COUNT_RAW_AFTER_UIDPK_FILTERED_QUERY: &str =
+    include_str!("queries/count_raw_after_uidpk_filtered.sql");
+const COUNT_RAW_AFTER_CURSOR_FILTERED_QUERY: &str =
+    include_str!("queries/count_raw_after_cursor_filtered.sql");
 const COUNT_RAW_AFTER_DATE_QUERY: &str = include_str!("queries/count_raw_after_date.sql");
+const COUNT_RAW_AFTER_UIDPK_QUERY: &str = include_str!("queries/count_raw_after_uidpk.sql");
+const COUNT_RAW_AFTER_CURSOR_QUERY: &str = include_str!("queries/count_raw_after_cursor.sql");
+const COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_DATE_QUERY: &str =
+    include_str!("queries/count_pending_by_merchant_account_after_date.sql");
+const COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_UIDPK_QUERY: &str =
+    include_str!("queries/count_pending_by_merchant_account_after_uidpk.sql");
+const COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_CURSOR_QUERY: &str =
+    include_str!("queries/count_pending_by_merchant_account_after_cursor.sql");
+const COUNT_PENDING_BY_EVENT_CODE_AFTER_DATE_QUERY: &str =
+    include_str!("queries/count_pending_by_event_code_after_date.sql");
+const COUNT_PENDING_BY_EVENT_CODE_AFTER_UIDPK_QUERY: &str =
+    include_str!("queries/count_pending_by_event_code_after_uidpk.sql");
+const COUNT_PENDING_BY_EVENT_CODE_AFTER_CURSOR_QUERY: &str =
+    include_str!("queries/count_pending_by_event_code_after_cursor.sql");
+const COUNT_PENDING_BY_DAY_AFTER_DATE_QUERY: &str =
+    include_str!("queries/count_pending_by_day_after_date.sql");
+const COUNT_PENDING_BY_DAY_AFTER_UIDPK_QUERY: &str =
+    include_str!("queries/count_pending_by_day_after_uidpk.sql");
+const COUNT_PENDING_BY_DAY_AFTER_CURSOR_QUERY: &str =
+    include_str!("queries/count_pending_by_day_after_cursor.sql");
+const SELECT_CHECKSUM_BUCKETS_QUERY: &str = include_str!("queries/select_checksum_buckets.sql");
+const SELECT_NOTIFICATION_CHECKSUMS_QUERY: &str =
+    include_str!("queries/select_notification_checksums.sql");
 const COUNT_RAW_BY_GUID_QUERY: &str = include_str!("queries/count_raw_by_guid.sql");
 const FIND_RAW_BY_GUID: &str = include_str!("queries/find_raw_by_guid.sql");
 
+const CREATE_CHECKPOINT_TABLE_QUERY: &str = include_str!("queries/create_checkpoint_table.sql");
+const CHECKPOINT_TABLE_EXISTS_QUERY: &str = include_str!("queries/checkpoint_table_exists.sql");
+const CHECKPOINT_STRATEGY_COLUMN_EXISTS_QUERY: &str =
+    include_str!("queries/checkpoint_strategy_column_exists.sql");
+const ADD_CHECKPOINT_STRATEGY_COLUMN_QUERY: &str =
+    include_str!("queries/add_checkpoint_strategy_column.sql");
+const FIND_CHECKPOINT_QUERY: &str = include_str!("queries/find_checkpoint.sql");
+const UPSERT_CHECKPOINT_QUERY: &str = include_str!("queries/upsert_checkpoint.sql");
+const DELETE_CHECKPOINT_QUERY: &str = include_str!("queries/delete_checkpoint.sql");
+
 pub async fn set_isolation_level<'e, E: MySqlExecutor<'e>>(exec: E) -> Result<()> {
-    let x = sqlx::query::<MySql>("SET TRANSACTION ISOLATION LEVEL READ UNCOMMITTED;")
+    sqlx::query::<MySql>("SET TRANSACTION ISOLATION LEVEL READ UNCOMMITTED;")
         .execute(exec)
         .await
-        .context("context")?;
+        .context("Error while setting transaction isolation level")?;
 
     Ok(())
 }
@@ -45,108 +122,340 @@ pub async fn test_conn<'e, E: MySqlExecutor<'e>>(exec: E) -> Result<()> {
         .fetch_one(exec)
         .await
         .map(|_| ())
-        .context("context")
+        .context("Error while testing connection")
 }
 
-pub async fn insert_raw_notification<'e, E: MySqlExecutor<'e>>(
-    exec: E,
-    raw: &RawNotification,
-) -> Result<()> {
-    sqlx::query::<MySql>(INSERT_RAW_QUERY)
-        .bind(&raw.uidpk)
-        .bind(&raw.guid)
-        .bind(raw.created_date)
-        .bind(raw.consumed_date)
-        .bind(&raw.consumed)
-        .bind(&raw.body)
-        .bind(&raw.client_id)
-        .bind(&raw.consume_success)
-        .execute(exec)
+/// Reads the server `max_allowed_packet` and derives the limits for multi-row INSERTs.
+pub async fn bulk_limits<'e, E: MySqlExecutor<'e>>(exec: E) -> Result<BulkLimits> {
+    sqlx::query_scalar::<_, u64>("select @@max_allowed_packet")
+        .fetch_one(exec)
         .await
-        .context("context")?;
-    Ok(())
+        .map(|packet| BulkLimits::new(packet as usize))
+        .context("Error while reading max_allowed_packet")
 }
 
-pub async fn insert_raw_notification_header<'e, E: MySqlExecutor<'e>>(
-    exec: E,
-    header: &RawNotificationHeader,
+async fn bulk_insert<'a, T>(
+    conn: &mut MySqlConnection,
+    insert: &str,
+    rows: &'a [T],
+    columns: usize,
+    limits: &BulkLimits,
+    size: impl Fn(&T) -> usize,
+    bind: impl Fn(Separated<'_, 'a, MySql, &'static str>, &'a T),
 ) -> Result<()> {
-    sqlx::query::<MySql>(INSERT_RAW_HEADER_QUERY)
-        .bind(&header.tadyen_raw_notification_uid)
-        .bind(&header.name.clone())
-        .bind(&header.value)
-        .execute(exec)
-        .await
-        .context("context")?;
+    for chunk in limits.split(rows, columns, size) {
+        let mut builder = QueryBuilder::<MySql>::new(insert);
+        builder.push_values(chunk, &bind);
+        builder
+            .build()
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Error while inserting {} rows", chunk.len()))?;
+    }
     Ok(())
 }
 
-pub async fn insert_item_data<'e, E: MySqlExecutor<'e>>(
-    exec: E,
-    data: &NotificationItemData,
+pub async fn insert_raw_notifications(
+    conn: &mut MySqlConnection,
+    raws: &[RawNotification],
+    limits: &BulkLimits,
 ) -> Result<()> {
-    sqlx::query::<MySql>(INSERT_ITEM_DATA_QUERY)
-        .bind(&data.notification_item_uid)
-        .bind(&data.name)
-        .bind(&data.value)
-        .execute(exec)
-        .await
-        .context("context")?;
-    Ok(())
+    bulk_insert(
+        conn,
+        BULK_INSERT_RAW_QUERY,
+        raws,
+        8,
+        limits,
+        |raw| raw.guid.len() + text_len(&raw.body) + raw.client_id.len(),
+        |mut b, raw| {
+            b.push_bind(&raw.uidpk)
+                .push_bind(&raw.guid)
+                .push_bind(raw.created_date)
+                .push_bind(raw.consumed_date)
+                .push_bind(&raw.consumed)
+                .push_bind(&raw.body)
+                .push_bind(&raw.client_id)
+                .push_bind(&raw.consume_success);
+        },
+    )
+    .await
 }
 
-pub async fn insert_item_operation<'e, E: MySqlExecutor<'e>>(
-    exec: E,
-    operation: &NotificationItemOperation,
+pub async fn insert_raw_notification_headers(
+    conn: &mut MySqlConnection,
+    headers: &[RawNotificationHeader],
+    limits: &BulkLimits,
 ) -> Result<()> {
-    sqlx::query::<MySql>(INSERT_ITEM_OPERATION_QUERY)
-        .bind(&operation.notification_item_uid)
-        .bind(&operation.operation)
-        .execute(exec)
-        .await
-        .context("context")?;
-    Ok(())
+    bulk_insert(
+        conn,
+        BULK_INSERT_RAW_HEADER_QUERY,
+        headers,
+        3,
+        limits,
+        |header| header.name.len() + text_len(&header.value),
+        |mut b, header| {
+            b.push_bind(&header.tadyen_raw_notification_uid)
+                .push_bind(&header.name)
+                .push_bind(&header.value);
+        },
+    )
+    .await
 }
 
-pub async fn insert_item<'e, E: MySqlExecutor<'e>>(exec: E, item: &NotificationItem) -> Result<()> {
-    sqlx::query::<MySql>(INSERT_ITEM_QUERY)
-        .bind(&item.uidpk)
-        .bind(&item.guid)
-        .bind(item.created_date)
-        .bind(&item.consume_success)
-        .bind(item.consumed_date)
-        .bind(&item.consumed)
-        .bind(&item.currency)
-        .bind(&item.amount)
-        .bind(&item.event_code)
-        .bind(item.event_date)
-        .bind(&item.merchant_account_code)
-        .bind(&item.merchant_reference)
-        .bind(&item.payment_method)
-        .bind(&item.psp_reference)
-        .bind(&item.reason)
-        .bind(&item.success)
-        .bind(&item.live)
-        .bind(&item.original_reference)
-        .bind(&item.client_id)
-        .bind(&item.raw_notification_item_guid)
-        .execute(exec)
-        .await
-        .context("context")?;
+pub async fn insert_items(
+    conn: &mut MySqlConnection,
+    items: &[NotificationItem],
+    limits: &BulkLimits,
+) -> Result<()> {
+    bulk_insert(
+        conn,
+        BULK_INSERT_ITEM_QUERY,
+        items,
+        20,
+        limits,
+        |item| {
+            item.guid.len()
+                + text_len(&item.currency)
+                + item.event_code.len()
+                + item.merchant_account_code.len()
+                + item.merchant_reference.len()
+                + text_len(&item.payment_method)
+                + item.psp_reference.len()
+                + text_len(&item.reason)
+                + text_len(&item.original_reference)
+                + item.client_id.len()
+                + item.raw_notification_item_guid.len()
+        },
+        |mut b, item| {
+            b.push_bind(&item.uidpk)
+                .push_bind(&item.guid)
+                .push_bind(item.created_date)
+                .push_bind(&item.consume_success)
+                .push_bind(item.consumed_date)
+                .push_bind(&item.consumed)
+                .push_bind(&item.currency)
+                .push_bind(&item.amount)
+                .push_bind(&item.event_code)
+                .push_bind(item.event_date)
+                .push_bind(&item.merchant_account_code)
+                .push_bind(&item.merchant_reference)
+                .push_bind(&item.payment_method)
+                .push_bind(&item.psp_reference)
+                .push_bind(&item.reason)
+                .push_bind(&item.success)
+                .push_bind(&item.live)
+                .push_bind(&item.original_reference)
+                .push_bind(&item.client_id)
+                .push_bind(&item.raw_notification_item_guid);
+        },
+    )
+    .await
+}
+
+pub async fn insert_items_data(
+    conn: &mut MySqlConnection,
+    datas: &[NotificationItemData],
+    limits: &BulkLimits,
+) -> Result<()> {
+    bulk_insert(
+        conn,
+        BULK_INSERT_ITEM_DATA_QUERY,
+        datas,
+        3,
+        limits,
+        |data| data.name.len() + text_len(&data.value),
+        |mut b, data| {
+            b.push_bind(&data.notification_item_uid)
+                .push_bind(&data.name)
+                .push_bind(&data.value);
+        },
+    )
+    .await
+}
+
+pub async fn insert_item_operations(
+    conn: &mut MySqlConnection,
+    operations: &[NotificationItemOperation],
+    limits: &BulkLimits,
+) -> Result<()> {
+    bulk_insert(
+        conn,
+        BULK_INSERT_ITEM_OPERATION_QUERY,
+        operations,
+        2,
+        limits,
+        |operation| operation.operation.len(),
+        |mut b, operation| {
+            b.push_bind(&operation.notification_item_uid)
+                .push_bind(&operation.operation);
+        },
+    )
+    .await
+}
+
+/// Builds `statement` followed by an `IN (...)` list of `values`.
+fn in_list<'a, V>(statement: &str, values: &'a [V]) -> QueryBuilder<'a, MySql>
+where
+    V: Encode<'a, MySql> + Type<MySql> + Sync,
+{
+    let mut builder = QueryBuilder::<MySql>::new(statement);
+    builder.push("(");
+    let mut separated = builder.separated(", ");
+    for value in values {
+        separated.push_bind(value);
+    }
+    separated.push_unseparated(")");
+    builder
+}
+
+/// Runs `select` followed by an `IN (...)` list of `values`, in chunks of `MAX_IN_VALUES`.
+async fn fetch_in<'a, T, V>(
+    conn: &mut MySqlConnection,
+    select: &str,
+    values: &'a [V],
+) -> Result<Vec<T>>
+where
+    T: for<'r> FromRow<'r, MySqlRow> + Send + Unpin,
+    V: Encode<'a, MySql> + Type<MySql> + Sync,
+{
+    let mut rows = vec![];
+    for chunk in values.chunks(MAX_IN_VALUES) {
+        rows.extend(
+            in_list(select, chunk)
+                .build_query_as::<T>()
+                .fetch_all(&mut *conn)
+                .await
+                .with_context(|| format!("Error while fetching {} keys", chunk.len()))?,
+        );
+    }
+    Ok(rows)
+}
+
+/// Runs `statement` followed by an `IN (...)` list of `values`, in chunks of `MAX_IN_VALUES`.
+/// Returns the number of affected rows.
+async fn execute_in<'a, V>(
+    conn: &mut MySqlConnection,
+    statement: &str,
+    values: &'a [V],
+) -> Result<u64>
+where
+    V: Encode<'a, MySql> + Type<MySql> + Sync,
+{
+    let mut affected = 0;
+    for chunk in values.chunks(MAX_IN_VALUES) {
+        affected += in_list(statement, chunk)
+            .build()
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Error while updating {} keys", chunk.len()))?
+            .rows_affected();
+    }
+    Ok(affected)
+}
+
+/// Deletes the whole notification trees of `guids`, children first.
+pub async fn delete_trees_by_guids(conn: &mut MySqlConnection, guids: &[String]) -> Result<()> {
+    execute_in(conn, DELETE_ITEM_OPERATIONS_BY_RAW_GUIDS_QUERY, guids).await?;
+    execute_in(conn, DELETE_ITEM_DATA_BY_RAW_GUIDS_QUERY, guids).await?;
+    execute_in(conn, DELETE_ITEMS_BY_RAW_GUIDS_QUERY, guids).await?;
+    execute_in(conn, DELETE_RAW_HEADERS_BY_RAW_GUIDS_QUERY, guids).await?;
+    execute_in(conn, DELETE_RAWS_BY_GUIDS_QUERY, guids).await?;
     Ok(())
 }
 
-pub async fn find_raw_after_uidpk<'e, E: MySqlExecutor<'e>>(
-    exec: E,
-    after: &BigDecimal,
-    batch_size: u8,
+pub async fn find_raws_by_guids(
+    conn: &mut MySqlConnection,
+    guids: &[String],
 ) -> Result<Vec<RawNotification>> {
-    sqlx::query_as::<_, RawNotification>(SELECT_RAW_TO_IMPORT_QUERY)
-        .bind(after)
-        .bind(batch_size)
-        .fetch_all(exec)
+    fetch_in(conn, SELECT_RAWS_BY_GUIDS_QUERY, guids).await
+}
+
+/// Returns which of `guids` already exist.
+pub async fn find_existing_raw_guids(
+    conn: &mut MySqlConnection,
+    guids: &[String],
+) -> Result<Vec<String>> {
+    fetch_in::<(String,), _>(conn, SELECT_EXISTING_RAW_GUIDS_QUERY, guids)
         .await
-        .context("context")
+        .map(|rows| rows.into_iter().map(|(guid,)| guid).collect())
+}
+
+pub async fn find_headers_by_raw_uids(
+    conn: &mut MySqlConnection,
+    raw_uids: &[BigDecimal],
+) -> Result<Vec<RawNotificationHeader>> {
+    fetch_in(conn, SELECT_RAW_HEADERS_BY_RAW_UIDS_QUERY, raw_uids).await
+}
+
+pub async fn find_items_by_raw_guids(
+    conn: &mut MySqlConnection,
+    raw_guids: &[String],
+) -> Result<Vec<NotificationItem>> {
+    fetch_in(conn, SELECT_ITEMS_BY_RAW_GUIDS_QUERY, raw_guids).await
+}
+
+pub async fn find_item_data_by_item_uids(
+    conn: &mut MySqlConnection,
+    item_uids: &[BigDecimal],
+) -> Result<Vec<NotificationItemData>> {
+    fetch_in(conn, SELECT_ITEM_DATA_BY_ITEM_UIDS_QUERY, item_uids).await
+}
+
+pub async fn find_item_operations_by_item_uids(
+    conn: &mut MySqlConnection,
+    item_uids: &[BigDecimal],
+) -> Result<Vec<NotificationItemOperation>> {
+    fetch_in(conn, SELECT_ITEM_OPERATIONS_BY_ITEM_UIDS_QUERY, item_uids).await
+}
+
+/// Statements `find_trees_by_guids` runs for every `MAX_IN_VALUES` guids.
+pub const TREE_QUERIES: usize = 5;
+
+/// Fetches the notification trees of `guids` with one set-based query per table.
+/// Guids that do not exist are left out.
+pub async fn find_trees_by_guids(
+    conn: &mut MySqlConnection,
+    guids: &[String],
+) -> Result<Vec<NotificationTree>> {
+    let raws = find_raws_by_guids(conn, guids).await?;
+    let raw_uids = raws.iter().map(|r| r.uidpk.clone()).collect::<Vec<_>>();
+    let raw_guids = raws.iter().map(|r| r.guid.clone()).collect::<Vec<_>>();
+
+    let headers = find_headers_by_raw_uids(conn, &raw_uids).await?;
+    let items = find_items_by_raw_guids(conn, &raw_guids).await?;
+    let item_uids = items.iter().map(|i| i.uidpk.clone()).collect::<Vec<_>>();
+    let data = find_item_data_by_item_uids(conn, &item_uids).await?;
+    let operations = find_item_operations_by_item_uids(conn, &item_uids).await?;
+
+    Ok(NotificationTree::assemble(
+        raws, headers, items, data, operations,
+    ))
+}
+
+/// Fetches one notification tree row by row.
+pub async fn find_tree_by_guid(
+    conn: &mut MySqlConnection,
+    guid: &str,
+) -> Result<Option<NotificationTree>> {
+    let Some(raw) = find_raw_by_guid(&mut *conn, guid).await? else {
+        return Ok(None);
+    };
+
+    let headers = find_headers(&mut *conn, &raw.uidpk).await?;
+    let mut items = vec![];
+    for item in find_items(&mut *conn, &raw.guid).await? {
+        items.push(NotificationItemTree {
+            data: find_item_data(&mut *conn, &item.uidpk).await?,
+            operations: find_item_operations(&mut *conn, &item.uidpk).await?,
+            item,
+        });
+    }
+
+    Ok(Some(NotificationTree {
+        raw,
+        headers,
+        items,
+    }))
 }
 
 pub async fn find_headers<'e, E: MySqlExecutor<'e>>(
@@ -157,7 +466,7 @@ pub async fn find_headers<'e, E: MySqlExecutor<'e>>(
         .bind(raw_uid)
         .fetch_all(exec)
         .await
-        .context("context")
+        .context("Error while fetching raw notification headers")
 }
 
 pub async fn find_item_data<'e, E: MySqlExecutor<'e>>(
@@ -168,7 +477,7 @@ pub async fn find_item_data<'e, E: MySqlExecutor<'e>>(
         .bind(item_uid)
         .fetch_all(exec)
         .await
-        .context("context")
+        .context("Error while fetching item data")
 }
 
 pub async fn find_items<'e, E: MySqlExecutor<'e>>(
@@ -179,7 +488,7 @@ pub async fn find_items<'e, E: MySqlExecutor<'e>>(
         .bind(raw_guid)
         .fetch_all(exec)
         .await
-        .context("context")
+        .context("Error while fetching items")
 }
 
 pub async fn find_item_operations<'e, E: MySqlExecutor<'e>>(
@@ -190,7 +499,7 @@ pub async fn find_item_operations<'e, E: MySqlExecutor<'e>>(
         .bind(item_uid)
         .fetch_all(exec)
         .await
-        .context("context")
+        .context("Error while fetching item operations")
 }
 
 pub async fn get_last_raw_created_date<'e, E: MySqlExecutor<'e>>(
@@ -199,31 +508,292 @@ pub async fn get_last_raw_created_date<'e, E: MySqlExecutor<'e>>(
     sqlx::query_scalar::<_, Option<NaiveDateTime>>(SELECT_LAST_RAW_CREATED_DATE_QUERY)
         .fetch_one(exec)
         .await
-        .context("context")
+        .context("Error while fetching last created date")
+}
+
+fn bind_window<'q, O>(
+    query: QueryAs<'q, MySql, O, MySqlArguments>,
+    window: &TimeWindow,
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    query
+        .bind(window.from)
+        .bind(window.from)
+        .bind(window.to)
+        .bind(window.to)
+}
+
+/// Placeholder of the conditions on items `i` in filtered queries.
+const ITEM_FILTER: &str = "/* item filter */";
+
+/// Puts the conditions of `filter` in place of the `ITEM_FILTER` placeholder of `query`, with an
+/// `IN (...)` list per attribute. Their values are bound by `bind_filter`.
+fn filtered(query: &str, filter: &ItemFilter) -> String {
+    let mut conditions = vec![];
+    for (column, values) in [
+        ("merchant_account_code", &filter.merchant_accounts),
+        ("event_code", &filter.event_codes),
+        ("payment_method", &filter.payment_methods),
+        ("currency", &filter.currencies),
+    ] {
+        if !values.is_empty() {
+            let list = vec!["?"; values.len()].join(", ");
+            conditions.push(format!("and i.{} in ({})", column, list));
+        }
+    }
+    for (column, value) in [("live", filter.live), ("success", filter.success)] {
+        if value.is_some() {
+            conditions.push(format!("and i.{} = ?", column));
+        }
+    }
+    query.replace(ITEM_FILTER, &conditions.join(" "))
+}
+
+fn bind_filter<'q, O>(
+    mut query: QueryAs<'q, MySql, O, MySqlArguments>,
+    filter: &'q ItemFilter,
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    for value in filter
+        .merchant_accounts
+        .iter()
+        .chain(&filter.event_codes)
+        .chain(&filter.payment_methods)
+        .chain(&filter.currencies)
+    {
+        query = query.bind(value);
+    }
+    for value in [filter.live, filter.success].into_iter().flatten() {
+        query = query.bind(value);
+    }
+    query
+}
+
+fn bind_cursor<'q, O>(
+    query: QueryAs<'q, MySql, O, MySqlArguments>,
+    strategy: CursorStrategy,
+    after: &'q Cursor,
+) -> QueryAs<'q, MySql, O, MySqlArguments> {
+    match strategy {
+        CursorStrategy::CreatedDate => query.bind(after.created_date),
+        CursorStrategy::Uidpk => query.bind(&after.uidpk),
+        CursorStrategy::Composite => query
+            .bind(after.created_date)
+            .bind(after.created_date)
+            .bind(&after.uidpk),
+    }
 }
 
-pub async fn find_raw_guid_after_created_date<'e, E: MySqlExecutor<'e>>(
+/// Fetches the keys of the next batch of raw notifications after `after`, inside `window` and
+/// with items matching `filter`, paging with `strategy`.
+pub async fn find_raw_keys_after<'e, E: MySqlExecutor<'e>>(
     exec: E,
-    after: &NaiveDateTime,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
     batch_size: u64,
-) -> Result<Vec<String>> {
-    sqlx::query_scalar::<_, String>(SELECT_RAW_AFTER_DATE_QUERY)
-        .bind(after)
+) -> Result<Vec<RawKey>> {
+    let query = match (strategy, filter.is_empty()) {
+        (CursorStrategy::CreatedDate, true) => SELECT_RAW_AFTER_DATE_QUERY,
+        (CursorStrategy::Uidpk, true) => SELECT_RAW_TO_IMPORT_QUERY,
+        (CursorStrategy::Composite, true) => SELECT_RAW_AFTER_CURSOR_QUERY,
+        (CursorStrategy::CreatedDate, false) => SELECT_RAW_AFTER_DATE_FILTERED_QUERY,
+        (CursorStrategy::Uidpk, false) => SELECT_RAW_AFTER_UIDPK_FILTERED_QUERY,
+        (CursorStrategy::Composite, false) => SELECT_RAW_AFTER_CURSOR_FILTERED_QUERY,
+    };
+    let query = filtered(query, filter);
+    let query = sqlx::query_as::<_, (String, NaiveDateTime, BigDecimal)>(&query);
+    let query = bind_filter(
+        bind_window(bind_cursor(query, strategy, after), window),
+        filter,
+    );
+    query
         .bind(batch_size)
         .fetch_all(exec)
         .await
-        .context("context")
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(guid, created_date, uidpk)| RawKey {
+                    guid,
+                    created_date,
+                    uidpk,
+                })
+                .collect()
+        })
+        .context("Error while fetching raw notification keys")
 }
 
-pub async fn count_raw_created_date<'e, E: MySqlExecutor<'e>>(
+pub async fn count_raw_after<'e, E: MySqlExecutor<'e>>(
     exec: E,
-    after: &NaiveDateTime,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
 ) -> Result<i64> {
-    sqlx::query_scalar::<_, i64>(COUNT_RAW_AFTER_DATE_QUERY)
-        .bind(after)
+    let query = match (strategy, filter.is_empty()) {
+        (CursorStrategy::CreatedDate, true) => COUNT_RAW_AFTER_DATE_QUERY,
+        (CursorStrategy::Uidpk, true) => COUNT_RAW_AFTER_UIDPK_QUERY,
+        (CursorStrategy::Composite, true) => COUNT_RAW_AFTER_CURSOR_QUERY,
+        (CursorStrategy::CreatedDate, false) => COUNT_RAW_AFTER_DATE_FILTERED_QUERY,
+        (CursorStrategy::Uidpk, false) => COUNT_RAW_AFTER_UIDPK_FILTERED_QUERY,
+        (CursorStrategy::Composite, false) => COUNT_RAW_AFTER_CURSOR_FILTERED_QUERY,
+    };
+    let query = filtered(query, filter);
+    let query = bind_cursor(sqlx::query_as::<_, (i64,)>(&query), strategy, after);
+    bind_filter(bind_window(query, window), filter)
         .fetch_one(exec)
         .await
-        .context("context")
+        .map(|(count,)| count)
+        .context("Error while counting raw notifications")
+}
+
+/// Counts the raw notifications after `after`, inside `window` and with items matching
+/// `filter`, per merchant account. A notification is counted once under each merchant account
+/// it has an item of, and under `-` when it has none.
+pub async fn count_pending_by_merchant_account<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<Vec<PendingGroup>> {
+    let query = match strategy {
+        CursorStrategy::CreatedDate => COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_DATE_QUERY,
+        CursorStrategy::Uidpk => COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_UIDPK_QUERY,
+        CursorStrategy::Composite => COUNT_PENDING_BY_MERCHANT_ACCOUNT_AFTER_CURSOR_QUERY,
+    };
+    count_pending_by_group(exec, query, strategy, after, window, filter)
+        .await
+        .context("Error while counting pending notifications by merchant account")
+}
+
+/// Counts the raw notifications after `after`, inside `window` and with items matching
+/// `filter`, per event code, the way `count_pending_by_merchant_account` does.
+pub async fn count_pending_by_event_code<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<Vec<PendingGroup>> {
+    let query = match strategy {
+        CursorStrategy::CreatedDate => COUNT_PENDING_BY_EVENT_CODE_AFTER_DATE_QUERY,
+        CursorStrategy::Uidpk => COUNT_PENDING_BY_EVENT_CODE_AFTER_UIDPK_QUERY,
+        CursorStrategy::Composite => COUNT_PENDING_BY_EVENT_CODE_AFTER_CURSOR_QUERY,
+    };
+    count_pending_by_group(exec, query, strategy, after, window, filter)
+        .await
+        .context("Error while counting pending notifications by event code")
+}
+
+async fn count_pending_by_group<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    query: &str,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> sqlx::Result<Vec<PendingGroup>> {
+    let query = filtered(query, filter);
+    let query = sqlx::query_as::<_, (String, i64)>(&query);
+    let query = bind_filter(
+        bind_window(bind_cursor(query, strategy, after), window),
+        filter,
+    );
+    query.fetch_all(exec).await.map(|rows| {
+        rows.into_iter()
+            .map(|(key, notifications)| PendingGroup { key, notifications })
+            .collect()
+    })
+}
+
+/// Counts the raw notifications after `after`, inside `window` and with items matching
+/// `filter`, per day of creation.
+pub async fn count_pending_by_day<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    strategy: CursorStrategy,
+    after: &Cursor,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<Vec<PendingDay>> {
+    let query = match strategy {
+        CursorStrategy::CreatedDate => COUNT_PENDING_BY_DAY_AFTER_DATE_QUERY,
+        CursorStrategy::Uidpk => COUNT_PENDING_BY_DAY_AFTER_UIDPK_QUERY,
+        CursorStrategy::Composite => COUNT_PENDING_BY_DAY_AFTER_CURSOR_QUERY,
+    };
+    let query = filtered(query, filter);
+    let query = sqlx::query_as::<_, (NaiveDate, i64)>(&query);
+    let query = bind_window(bind_cursor(query, strategy, after), window).bind(!filter.is_empty());
+    bind_filter(query, filter)
+        .fetch_all(exec)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(day, notifications)| PendingDay { day, notifications })
+                .collect()
+        })
+        .context("Error while counting pending notifications by day")
+}
+
+/// Sums the checksums of the notifications inside `window` with items matching `filter`, per
+/// bucket of created dates formatted with the MySQL `date_format` pattern `format`.
+pub async fn find_checksum_buckets<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    format: &str,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<Vec<ChecksumBucket>> {
+    let query = filtered(SELECT_CHECKSUM_BUCKETS_QUERY, filter);
+    let query = sqlx::query_as::<_, (String, i64, u64)>(&query).bind(format);
+    let query = bind_filter(bind_window(query, window).bind(!filter.is_empty()), filter);
+    query
+        .fetch_all(exec)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(bucket, notifications, checksum)| ChecksumBucket {
+                    bucket,
+                    notifications,
+                    checksum,
+                })
+                .collect()
+        })
+        .context("Error while computing checksum buckets")
+}
+
+/// Computes the checksum of each notification inside `window` with items matching `filter`.
+pub async fn find_notification_checksums<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    window: &TimeWindow,
+    filter: &ItemFilter,
+) -> Result<Vec<NotificationChecksum>> {
+    let query = filtered(SELECT_NOTIFICATION_CHECKSUMS_QUERY, filter);
+    let query = sqlx::query_as::<_, (String, u64)>(&query);
+    let query = bind_filter(bind_window(query, window).bind(!filter.is_empty()), filter);
+    query
+        .fetch_all(exec)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(guid, checksum)| NotificationChecksum { guid, checksum })
+                .collect()
+        })
+        .context("Error while computing notification checksums")
+}
+
+/// Returns the cursor of the last raw notification according to `strategy`.
+pub async fn get_last_raw_cursor<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    strategy: CursorStrategy,
+) -> Result<Option<Cursor>> {
+    let query = match strategy {
+        CursorStrategy::Uidpk => SELECT_LAST_RAW_UIDPK_QUERY,
+        CursorStrategy::CreatedDate | CursorStrategy::Composite => SELECT_LAST_RAW_CURSOR_QUERY,
+    };
+    sqlx::query_as::<_, (NaiveDateTime, BigDecimal)>(query)
+        .fetch_optional(exec)
+        .await
+        .map(|row| row.map(|(created_date, uidpk)| Cursor::new(created_date, uidpk)))
+        .context("Error while fetching last raw notification cursor")
 }
 
 pub async fn exists_raw_by_guid<'e, E: MySqlExecutor<'e>>(exec: E, guid: &String) -> Result<bool> {
@@ -231,7 +801,7 @@ pub async fn exists_raw_by_guid<'e, E: MySqlExecutor<'e>>(exec: E, guid: &String
         .bind(guid)
         .fetch_one(exec)
         .await
-        .context("context")?;
+        .context("Error while checking raw notification existence")?;
     Ok(count > 0)
 }
 
@@ -243,7 +813,83 @@ pub async fn find_raw_by_guid<'e, E: MySqlExecutor<'e>>(
         .bind(guid)
         .fetch_optional(exec)
         .await
-        .context("context")
+        .context("Error while fetching raw notification")
+}
+
+/// Creates the checkpoint table, or adds the cursor strategy column to one created before it
+/// was recorded.
+pub async fn create_checkpoint_table(pool: &MySqlPool) -> Result<()> {
+    sqlx::query::<MySql>(CREATE_CHECKPOINT_TABLE_QUERY)
+        .execute(pool)
+        .await
+        .context("Error while creating checkpoint table")?;
+
+    let columns = sqlx::query_scalar::<_, i64>(CHECKPOINT_STRATEGY_COLUMN_EXISTS_QUERY)
+        .fetch_one(pool)
+        .await
+        .context("Error while looking for checkpoint strategy column")?;
+    if columns == 0 {
+        sqlx::query::<MySql>(ADD_CHECKPOINT_STRATEGY_COLUMN_QUERY)
+            .execute(pool)
+            .await
+            .context("Error while adding checkpoint strategy column")?;
+    }
+    Ok(())
+}
+
+pub async fn checkpoint_table_exists<'e, E: MySqlExecutor<'e>>(exec: E) -> Result<bool> {
+    let count = sqlx::query_scalar::<_, i64>(CHECKPOINT_TABLE_EXISTS_QUERY)
+        .fetch_one(exec)
+        .await
+        .context("Error while looking for checkpoint table")?;
+    Ok(count > 0)
+}
+
+pub async fn find_checkpoint<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    key: &CheckpointKey,
+) -> Result<Option<SyncCheckpoint>> {
+    sqlx::query_as::<_, SyncCheckpoint>(FIND_CHECKPOINT_QUERY)
+        .bind(&key.source)
+        .bind(&key.target)
+        .bind(&key.client_id)
+        .fetch_optional(exec)
+        .await
+        .context("Error while fetching checkpoint")
+}
+
+pub async fn upsert_checkpoint<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    key: &CheckpointKey,
+    created_date: &NaiveDateTime,
+    uidpk: &BigDecimal,
+    strategy: Option<CursorStrategy>,
+) -> Result<()> {
+    sqlx::query::<MySql>(UPSERT_CHECKPOINT_QUERY)
+        .bind(&key.source)
+        .bind(&key.target)
+        .bind(&key.client_id)
+        .bind(created_date)
+        .bind(uidpk)
+        .bind(strategy.map(|strategy| strategy.name()))
+        .execute(exec)
+        .await
+        .context("Error while saving checkpoint")?;
+    Ok(())
+}
+
+pub async fn delete_checkpoint<'e, E: MySqlExecutor<'e>>(
+    exec: E,
+    key: &CheckpointKey,
+) -> Result<bool> {
+    let result = sqlx::query::<MySql>(DELETE_CHECKPOINT_QUERY)
+        .bind(&key.source)
+        .bind(&key.target)
+        .bind(&key.client_id)
+        .execute(exec)
+        .await
+        .context("Error while deleting checkpoint")?;
+    Ok(result.rows_affected() > 0)
 }
 
 #[derive(Clone)]
@@ -252,6 +898,21 @@ pub struct Pools {
     pub target: MySqlPool,
 }
 
+#[cfg(test)]
+impl Pools {
+    /// Pools that never connect unless used, for tests that need no database.
+    pub fn lazy() -> Self {
+        Pools {
+            source: MySqlPoolOptions::new()
+                .connect_lazy("mysql://localhost/source")
+                .unwrap(),
+            target: MySqlPoolOptions::new()
+                .connect_lazy("mysql://localhost/target")
+                .unwrap(),
+        }
+    }
+}
+
 impl TryFrom<&DatabaseStatusArgs> for Pools {
     type Error = anyhow::Error;
 
@@ -270,6 +931,33 @@ impl TryFrom<&DatabaseSyncArgs> for Pools {
     }
 }
 
+impl TryFrom<&DatabaseVerifyArgs> for Pools {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &DatabaseVerifyArgs) -> std::result::Result<Self, Self::Error> {
+        let common_args = &value.common_args;
+        common_args.try_into()
+    }
+}
+
+impl TryFrom<&DatabaseInspectArgs> for Pools {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &DatabaseInspectArgs) -> std::result::Result<Self, Self::Error> {
+        let common_args = &value.common_args;
+        common_args.try_into()
+    }
+}
+
+impl TryFrom<&DatabaseRepairArgs> for Pools {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &DatabaseRepairArgs) -> std::result::Result<Self, Self::Error> {
+        let common_args = &value.common_args;
+        common_args.try_into()
+    }
+}
+
 impl TryFrom<&DatabaseWatchArgs> for Pools {
     type Error = anyhow::Error;
 
@@ -279,17 +967,26 @@ impl TryFrom<&DatabaseWatchArgs> for Pools {
     }
 }
 
+impl TryFrom<&DatabaseCheckpointArgs> for Pools {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &DatabaseCheckpointArgs) -> std::result::Result<Self, Self::Error> {
+        let common_args = &value.common_args;
+        common_args.try_into()
+    }
+}
+
 impl TryFrom<&CommonsDatabaseArgs> for Pools {
     type Error = anyhow::Error;
 
     fn try_from(value: &CommonsDatabaseArgs) -> Result<Self, Self::Error> {
         let source = MySqlPoolOptions::new()
-            .max_connections(30)
+            .max_connections(value.source_max_connections)
             .acquire_timeout(Duration::from_secs(
                 value.timeout.context("Timeout time not defined")?,
             ))
             .connect_lazy(
-                &value
+                value
                     .source_url
                     .as_ref()
                     .context("Source connection url not defined")?,
@@ -297,12 +994,12 @@ impl TryFrom<&CommonsDatabaseArgs> for Pools {
             .context("Error while creating source connection pool")?;
 
         let target = MySqlPoolOptions::new()
-            .max_connections(30)
+            .max_connections(value.target_max_connections)
             .acquire_timeout(Duration::from_secs(
                 value.timeout.context("Timeout time not defined")?,
             ))
             .connect_lazy(
-                &value
+                value
                     .target_url
                     .as_ref()
                     .context("target connection url not defined")?,
@@ -311,3 +1008,29 @@ impl TryFrom<&CommonsDatabaseArgs> for Pools {
         Ok(Pools { source, target })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filtered() {
+        let query = "where i.raw_notification_item_guid = r.guid /* item filter */)";
+        assert_eq!(
+            filtered(query, &ItemFilter::default()),
+            "where i.raw_notification_item_guid = r.guid )"
+        );
+
+        let filter = ItemFilter {
+            merchant_accounts: vec!["shop,eu".to_owned(), "market".to_owned()],
+            currencies: vec!["EUR".to_owned()],
+            live: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            filtered(query, &filter),
+            "where i.raw_notification_item_guid = r.guid and i.merchant_account_code in (?, ?) \
+            and i.currency in (?) and i.live = ?)"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::{future::Future, io::ErrorKind, time::Duration};
+
+use anyhow::Result;
+use log::warn;
+use rand::Rng;
+use sqlx::mysql::MySqlDatabaseError;
+
+use crate::settings::Settings;
+
+/// Lock wait timeout exceeded
+const ER_LOCK_WAIT_TIMEOUT: u16 = 1205;
+/// Deadlock found when trying to get lock
+const ER_LOCK_DEADLOCK: u16 = 1213;
+/// Server shutdown in progress
+const ER_SERVER_SHUTDOWN: u16 = 1053;
+/// Too many connections
+const ER_CON_COUNT_ERROR: u16 = 1040;
+/// Query execution was interrupted, the connection was killed
+const ER_QUERY_INTERRUPTED: u16 = 1317;
+/// MySQL server has gone away
+const CR_SERVER_GONE_ERROR: u16 = 2006;
+/// Lost connection to MySQL server during query
+const CR_SERVER_LOST: u16 = 2013;
+
+const TRANSIENT_ERROR_CODES: [u16; 7] = [
+    ER_LOCK_WAIT_TIMEOUT,
+    ER_LOCK_DEADLOCK,
+    ER_SERVER_SHUTDOWN,
+    ER_CON_COUNT_ERROR,
+    ER_QUERY_INTERRUPTED,
+    CR_SERVER_GONE_ERROR,
+    CR_SERVER_LOST,
+];
+
+/// How many times, and how far apart, a batch failing with a transient error is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl From<&Settings> for RetryPolicy {
+    fn from(settings: &Settings) -> Self {
+        let default = Self::default();
+        Self {
+            max_attempts: settings
+                .retry_max_attempts
+                .unwrap_or(default.max_attempts)
+                .max(1),
+            base_delay: settings
+                .retry_base_delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_delay),
+            max_delay: settings
+                .retry_max_delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.max_delay),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff capped at `max_delay`, with its upper half randomized so that
+    /// concurrent workers do not retry in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// Runs `operation` until it succeeds, fails with a permanent error or runs out of attempts.
+    pub async fn retry<T, F, Fut>(&self, what: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} failed with a transient error (attempt {}/{}), retrying in {:?}: {:#}",
+                        what, attempt, self.max_attempts, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Whether `error` was caused by a database error worth retrying: deadlocks, lock wait
+/// timeouts, dropped connections and pool acquire timeouts.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<sqlx::Error>())
+        .any(is_transient_sqlx)
+}
+
+fn is_transient_sqlx(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::TimedOut
+        ),
+        sqlx::Error::Database(e) => e
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .map(|e| TRANSIENT_ERROR_CODES.contains(&e.number()))
+            .unwrap_or_default(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_is_transient() {
+        let timed_out = Err::<(), _>(sqlx::Error::PoolTimedOut).context("Error while fetching");
+        let reset = Err::<(), _>(sqlx::Error::Io(ErrorKind::ConnectionReset.into()))
+            .context("Error while inserting");
+        let not_found = Err::<(), _>(sqlx::Error::RowNotFound).context("Error while fetching");
+
+        assert!(is_transient(&timed_out.unwrap_err()));
+        assert!(is_transient(&reset.unwrap_err()));
+        assert!(!is_transient(&not_found.unwrap_err()));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+
+        let first = policy.delay(1);
+        let capped = policy.delay(10);
+
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+}
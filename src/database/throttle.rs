@@ -0,0 +1,139 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime};
+use log::info;
+
+/// Daily time range, in local time, during which source database is not read. The end is
+/// exclusive and may be earlier than the start for a range crossing midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauseWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl PauseWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Time left from `time` until the end of the window.
+    pub fn remaining(&self, time: NaiveTime) -> Duration {
+        let left = self.end.signed_duration_since(time);
+        let left = if left < chrono::Duration::zero() {
+            left + chrono::Duration::days(1)
+        } else {
+            left
+        };
+        left.to_std().unwrap_or_default()
+    }
+}
+
+/// Paces operations to a number per second, shared by every worker.
+#[derive(Debug)]
+struct Rate {
+    per_second: f64,
+    next: Mutex<Instant>,
+}
+
+impl Rate {
+    fn new(per_second: u32) -> Self {
+        Self {
+            per_second: per_second.max(1) as f64,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves `amount` operations and returns when they may start.
+    fn reserve(&self, amount: usize) -> Instant {
+        let mut next = self.next.lock().expect("rate lock poisoned");
+        let start = (*next).max(Instant::now());
+        *next = start + Duration::from_secs_f64(amount as f64 / self.per_second);
+        start
+    }
+}
+
+/// Limits the load put on source database: rows and queries per second, and daily windows
+/// during which nothing new is read.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    rows: Option<Rate>,
+    queries: Option<Rate>,
+    pauses: Vec<PauseWindow>,
+}
+
+impl Throttle {
+    pub fn new(
+        max_rows_per_sec: Option<u32>,
+        max_queries_per_sec: Option<u32>,
+        pauses: Vec<PauseWindow>,
+    ) -> Self {
+        Self {
+            rows: max_rows_per_sec.map(Rate::new),
+            queries: max_queries_per_sec.map(Rate::new),
+            pauses,
+        }
+    }
+
+    /// Waits for any pause window to end and for `count` queries to be allowed.
+    pub async fn queries(&self, count: usize) {
+        self.wait_pauses().await;
+        if let Some(rate) = &self.queries {
+            tokio::time::sleep_until(rate.reserve(count).into()).await;
+        }
+    }
+
+    /// Accounts for `count` rows read, waiting while earlier reads are over the limit.
+    pub async fn rows(&self, count: usize) {
+        if let Some(rate) = &self.rows {
+            tokio::time::sleep_until(rate.reserve(count).into()).await;
+        }
+    }
+
+    async fn wait_pauses(&self) {
+        loop {
+            let now = Local::now().time();
+            let Some(pause) = self.pauses.iter().find(|pause| pause.contains(now)) else {
+                return;
+            };
+            info!(
+                "Source database reads paused until {}",
+                pause.end.format("%H:%M")
+            );
+            tokio::time::sleep(pause.remaining(now)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_pause_window() {
+        let evening = PauseWindow {
+            start: time(18, 0),
+            end: time(22, 0),
+        };
+        let night = PauseWindow {
+            start: time(23, 0),
+            end: time(2, 0),
+        };
+
+        assert!(evening.contains(time(18, 0)));
+        assert!(!evening.contains(time(22, 0)));
+        assert!(night.contains(time(1, 30)));
+        assert!(!night.contains(time(12, 0)));
+        assert_eq!(night.remaining(time(23, 30)), Duration::from_secs(150 * 60));
+    }
+}
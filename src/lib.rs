@@ -1,3 +1,4 @@
 pub mod commands;
 pub mod database;
+pub mod logging;
 pub mod settings;
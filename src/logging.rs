@@ -0,0 +1,225 @@
+use std::{
+    fmt::Display,
+    future::{poll_fn, Future},
+    path::Path,
+    pin::pin,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{LevelFilter, Record};
+use log4rs::{
+    append::{
+        console::{ConsoleAppender, Target},
+        rolling_file::{
+            policy::compound::{
+                roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger, CompoundPolicy,
+            },
+            RollingFileAppender,
+        },
+        Append,
+    },
+    config::{Appender, Logger, Root},
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
+    Config,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::{database::handlers::progress, root::GlobalOpts},
+    settings::Settings,
+};
+
+const TEXT_PATTERN: &str = "{d(%Y-%m-%d %H:%M:%S)} {h({l:<5})} {t} - {m}{n}";
+const DEFAULT_FILE_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_FILE_COUNT: u32 = 5;
+
+/// How log records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per record
+    #[default]
+    Text,
+
+    /// One JSON object per record, with the guid and batch fields under `mdc`
+    Json,
+}
+
+/// Sets up logging from `settings` and the verbosity flags.
+///
+/// Records go to stderr, so command output on stdout stays parseable, and to a rotating file
+/// when `log_file` is set. A `log_config` log4rs YAML file replaces all of this.
+pub fn init(settings: &Settings, opts: &GlobalOpts) -> Result<()> {
+    if let Some(path) = &settings.log_config {
+        return log4rs::init_file(path, Default::default())
+            .with_context(|| format!("Error while loading log config: {}", path.display()));
+    }
+
+    let base = match &settings.log_level {
+        Some(level) => level
+            .parse()
+            .with_context(|| format!("Invalid log level: {}", level))?,
+        None => LevelFilter::Info,
+    };
+    let level = verbosity(base, opts);
+    let format = settings.log_format.unwrap_or_default();
+
+    let mut appenders = vec!["console"];
+    let mut builder = Config::builder().appender(
+        Appender::builder().build(
+            "console",
+            Box::new(AroundProgress(
+                ConsoleAppender::builder()
+                    .target(Target::Stderr)
+                    .encoder(encoder(format))
+                    .build(),
+            )),
+        ),
+    );
+    if let Some(path) = &settings.log_file {
+        let file = rolling_file(
+            path,
+            format,
+            settings
+                .log_file_max_size_mb
+                .unwrap_or(DEFAULT_FILE_MAX_SIZE_MB),
+            settings.log_file_count.unwrap_or(DEFAULT_FILE_COUNT),
+        )?;
+        builder = builder.appender(Appender::builder().build("file", Box::new(file)));
+        appenders.push("file");
+    }
+
+    // sqlx logs every statement at info level, which is only wanted when tracing.
+    let sqlx = if level >= LevelFilter::Trace {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Warn.min(level)
+    };
+    let config = builder
+        .logger(Logger::builder().build("sqlx", sqlx))
+        .build(Root::builder().appenders(appenders).build(level))
+        .context("Error while building log config")?;
+    log4rs::init_config(config).context("Error while initializing logging")?;
+    Ok(())
+}
+
+/// Raises `base` one level per `-v`, to at least debug with `--debug`, and lowers it one level
+/// per `-q`.
+fn verbosity(base: LevelFilter, opts: &GlobalOpts) -> LevelFilter {
+    let levels = LevelFilter::iter().collect::<Vec<_>>();
+    let mut ix = levels.iter().position(|level| *level == base).unwrap_or(3) as i32;
+    ix += opts.verbose as i32 - opts.quiet as i32;
+    let mut level = levels[ix.clamp(0, levels.len() as i32 - 1) as usize];
+    if opts.debug {
+        level = level.max(LevelFilter::Debug);
+    }
+    level
+}
+
+/// Console appender that hides the progress bars while it writes a record, so the terminal
+/// does not mix the two.
+#[derive(Debug)]
+struct AroundProgress(ConsoleAppender);
+
+impl Append for AroundProgress {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        progress::suspend(|| self.0.append(record))
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+fn encoder(format: LogFormat) -> Box<dyn Encode> {
+    match format {
+        LogFormat::Text => Box::new(PatternEncoder::new(TEXT_PATTERN)),
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+    }
+}
+
+fn rolling_file(
+    path: &Path,
+    format: LogFormat,
+    max_size_mb: u64,
+    count: u32,
+) -> Result<RollingFileAppender> {
+    let pattern = format!("{}.{{}}", path.display());
+    let roller = FixedWindowRoller::builder()
+        .build(&pattern, count.max(1))
+        .context("Error while configuring log rotation")?;
+    let policy = CompoundPolicy::new(
+        Box::new(SizeTrigger::new(max_size_mb.max(1) * 1024 * 1024)),
+        Box::new(roller),
+    );
+    RollingFileAppender::builder()
+        .encoder(encoder(format))
+        .build(path, Box::new(policy))
+        .with_context(|| format!("Error while opening log file: {}", path.display()))
+}
+
+/// Runs `log` with `fields` attached to the records it emits. They are written under `mdc`
+/// by the JSON format.
+pub fn with_fields(fields: &[(&str, &dyn Display)], log: impl FnOnce()) {
+    let _fields = log_mdc::extend_scoped(
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    log();
+}
+
+/// Runs `future` with `fields` attached to every record it emits, whichever thread polls it.
+pub fn in_fields<F: Future>(
+    fields: &[(&str, &dyn Display)],
+    future: F,
+) -> impl Future<Output = F::Output> {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    async move {
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            let _fields = log_mdc::extend_scoped(fields.iter().cloned());
+            future.as_mut().poll(cx)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(debug: bool, verbose: u8, quiet: u8) -> GlobalOpts {
+        GlobalOpts {
+            debug,
+            verbose,
+            quiet,
+        }
+    }
+
+    #[test]
+    fn test_verbosity() {
+        let info = LevelFilter::Info;
+        assert_eq!(verbosity(info, &opts(false, 0, 0)), LevelFilter::Info);
+        assert_eq!(verbosity(info, &opts(false, 1, 0)), LevelFilter::Debug);
+        assert_eq!(verbosity(info, &opts(false, 5, 0)), LevelFilter::Trace);
+        assert_eq!(verbosity(info, &opts(false, 0, 2)), LevelFilter::Error);
+        assert_eq!(verbosity(info, &opts(false, 0, 9)), LevelFilter::Off);
+        assert_eq!(verbosity(info, &opts(true, 0, 2)), LevelFilter::Debug);
+    }
+
+    #[tokio::test]
+    async fn test_in_fields() {
+        let batch = in_fields(&[("batch", &7)], async {
+            tokio::task::yield_now().await;
+            log_mdc::get("batch", |value| value.map(str::to_owned))
+        })
+        .await;
+        assert_eq!(batch.as_deref(), Some("7"));
+        assert!(log_mdc::get("batch", |value| value.is_none()));
+    }
+}
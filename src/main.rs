@@ -1,9 +1,13 @@
 use adyen_sync::{
     commands::{
         config::handlers::config_handler,
-        database::handlers::database_handler,
+        database::handlers::{
+            database_handler, dead_letter::DeadLettered, shutdown::Interrupted,
+            verify_handler::Mismatched,
+        },
         root::{Cli, Command},
     },
+    logging,
     settings::Settings,
 };
 use clap::Parser;
@@ -12,9 +16,26 @@ use clap::Parser;
 async fn main() -> Result<(), anyhow::Error> {
     let mut config = Settings::load()?;
     let cli = Cli::parse();
+    logging::init(&config, &cli.global_opts)?;
 
-    match cli.command {
+    let result = match cli.command {
         Command::Config(command) => config_handler(&mut config, &cli.global_opts, command).await,
-        Command::Database(command) => database_handler(&config, &cli.global_opts, command).await,
+        Command::Database(command) => database_handler(&config, &cli.global_opts, *command).await,
+    };
+
+    if let Err(e) = &result {
+        if e.downcast_ref::<DeadLettered>().is_some() {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(DeadLettered::EXIT_CODE);
+        }
+        if e.downcast_ref::<Mismatched>().is_some() {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(Mismatched::EXIT_CODE);
+        }
+        if e.downcast_ref::<Interrupted>().is_some() {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(Interrupted::EXIT_CODE);
+        }
     }
+    result
 }
@@ -1,3 +1,4 @@
+use crate::logging::LogFormat;
 use anyhow::{Context, Result};
 use config::File;
 use serde::{Deserialize, Serialize};
@@ -20,6 +21,15 @@ pub struct Settings {
     pub target_url: Option<String>,
     pub timeout: Option<u64>,
     pub target_client_id: Option<String>,
+    pub retry_max_attempts: Option<u32>,
+    pub retry_base_delay_ms: Option<u64>,
+    pub retry_max_delay_ms: Option<u64>,
+    pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub log_file: Option<PathBuf>,
+    pub log_file_max_size_mb: Option<u64>,
+    pub log_file_count: Option<u32>,
+    pub log_config: Option<PathBuf>,
 }
 
 impl Default for Settings {
@@ -29,6 +39,15 @@ impl Default for Settings {
             target_url: Default::default(),
             timeout: Some(10),
             target_client_id: Default::default(),
+            retry_max_attempts: Some(5),
+            retry_base_delay_ms: Some(200),
+            retry_max_delay_ms: Some(30_000),
+            log_level: Some("info".to_owned()),
+            log_format: Some(LogFormat::Text),
+            log_file: None,
+            log_file_max_size_mb: Some(10),
+            log_file_count: Some(5),
+            log_config: None,
         }
     }
 }
@@ -108,6 +127,60 @@ impl Settings {
             self.target_client_id = Some(target_client_id.clone())
         }
     }
+
+    pub fn retry_max_attempts(&mut self, retry_max_attempts: &Option<u32>) {
+        if let Some(retry_max_attempts) = retry_max_attempts {
+            self.retry_max_attempts = Some(*retry_max_attempts)
+        }
+    }
+
+    pub fn retry_base_delay_ms(&mut self, retry_base_delay_ms: &Option<u64>) {
+        if let Some(retry_base_delay_ms) = retry_base_delay_ms {
+            self.retry_base_delay_ms = Some(*retry_base_delay_ms)
+        }
+    }
+
+    pub fn retry_max_delay_ms(&mut self, retry_max_delay_ms: &Option<u64>) {
+        if let Some(retry_max_delay_ms) = retry_max_delay_ms {
+            self.retry_max_delay_ms = Some(*retry_max_delay_ms)
+        }
+    }
+
+    pub fn log_level(&mut self, log_level: &Option<String>) {
+        if let Some(log_level) = log_level {
+            self.log_level = Some(log_level.clone())
+        }
+    }
+
+    pub fn log_format(&mut self, log_format: &Option<LogFormat>) {
+        if let Some(log_format) = log_format {
+            self.log_format = Some(*log_format)
+        }
+    }
+
+    pub fn log_file(&mut self, log_file: &Option<PathBuf>) {
+        if let Some(log_file) = log_file {
+            self.log_file = Some(log_file.clone())
+        }
+    }
+
+    pub fn log_file_max_size_mb(&mut self, log_file_max_size_mb: &Option<u64>) {
+        if let Some(log_file_max_size_mb) = log_file_max_size_mb {
+            self.log_file_max_size_mb = Some(*log_file_max_size_mb)
+        }
+    }
+
+    pub fn log_file_count(&mut self, log_file_count: &Option<u32>) {
+        if let Some(log_file_count) = log_file_count {
+            self.log_file_count = Some(*log_file_count)
+        }
+    }
+
+    pub fn log_config(&mut self, log_config: &Option<PathBuf>) {
+        if let Some(log_config) = log_config {
+            self.log_config = Some(log_config.clone())
+        }
+    }
 }
 
 #[cfg(test)]
@@ -116,4 +189,14 @@ mod tests {
 
     #[test]
     fn test_globals() {}
+
+    #[test]
+    fn test_global_overrides() {
+        let mut settings = Settings::default();
+        settings.timeout(&None);
+        settings.target_client_id(&Some("client".to_owned()));
+
+        assert_eq!(settings.timeout, Some(10));
+        assert_eq!(settings.target_client_id.as_deref(), Some("client"));
+    }
 }